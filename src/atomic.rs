@@ -0,0 +1,189 @@
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A uniquely named file next to its final destination, removed on drop
+/// unless it was persisted. Being in the same directory keeps the final
+/// rename atomic.
+pub struct TempFile {
+    path: PathBuf,
+    persisted: bool,
+}
+
+impl TempFile {
+    /// Writes `contents` with `mode` to a new temp file and syncs it.
+    pub fn create(target: &Path, contents: &[u8], mode: u32) -> io::Result<Self> {
+        let (mut file, temp) = Self::reserve(target, |path| {
+            OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(mode)
+                .open(path)
+        })?;
+        file.write_all(contents)?;
+        // The mode passed to open is filtered by the umask.
+        file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(mode))?;
+        file.sync_all()?;
+        Ok(temp)
+    }
+
+    /// Creates a temp symlink pointing at `link_target`.
+    pub fn symlink(target: &Path, link_target: &Path) -> io::Result<Self> {
+        let ((), temp) =
+            Self::reserve(target, |path| std::os::unix::fs::symlink(link_target, path))?;
+        Ok(temp)
+    }
+
+    fn reserve<T>(
+        target: &Path,
+        mut create: impl FnMut(&Path) -> io::Result<T>,
+    ) -> io::Result<(T, Self)> {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let dir = target.parent().unwrap_or(Path::new("."));
+        let name = target.file_name().unwrap_or_default();
+
+        let mut attempts = 0;
+        loop {
+            let mut temp_name = OsString::from(".");
+            temp_name.push(name);
+            temp_name.push(format!(
+                ".{}.{}.tmp",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::Relaxed)
+            ));
+            let path = dir.join(temp_name);
+            match create(&path) {
+                Ok(value) => {
+                    let temp = Self {
+                        path,
+                        persisted: false,
+                    };
+                    return Ok((value, temp));
+                }
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists && attempts < 100 => {
+                    attempts += 1;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Moves the temp file to `target` and syncs the directory. Without
+    /// `replace` this fails with `AlreadyExists` if `target` exists, without
+    /// a window in which another writer could slip in.
+    pub fn persist(mut self, target: &Path, replace: bool) -> io::Result<()> {
+        if replace {
+            fs::rename(&self.path, target)?;
+        } else {
+            rename_noreplace(&self.path, target)?;
+        }
+        self.persisted = true;
+        sync_dir(target.parent().unwrap_or(Path::new(".")))
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.persisted {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// `rename` that refuses to replace an existing `to`: `renameat2` with
+/// `RENAME_NOREPLACE`, falling back to `link` + `unlink` on filesystems that
+/// do not support the flag.
+pub fn rename_noreplace(from: &Path, to: &Path) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        let from_c = std::ffi::CString::new(from.as_os_str().as_bytes())?;
+        let to_c = std::ffi::CString::new(to.as_os_str().as_bytes())?;
+        // SAFETY: both paths are valid NUL-terminated strings.
+        let result = unsafe {
+            libc::renameat2(
+                libc::AT_FDCWD,
+                from_c.as_ptr(),
+                libc::AT_FDCWD,
+                to_c.as_ptr(),
+                libc::RENAME_NOREPLACE,
+            )
+        };
+        if result == 0 {
+            return Ok(());
+        }
+        let err = io::Error::last_os_error();
+        if !matches!(err.raw_os_error(), Some(libc::EINVAL | libc::ENOSYS)) {
+            return Err(err);
+        }
+    }
+
+    fs::hard_link(from, to)?;
+    fs::remove_file(from)
+}
+
+pub fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leftover_temp_files(dir: &Path) -> Vec<PathBuf> {
+        fs::read_dir(dir)
+            .expect("read dir")
+            .map(|entry| entry.expect("entry").path())
+            .filter(|path| path.to_string_lossy().ends_with(".tmp"))
+            .collect()
+    }
+
+    #[test]
+    fn temp_names_do_not_depend_on_the_extension() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let first = TempFile::create(&dir.path().join("foo.cli"), b"a", 0o644).expect("temp");
+        let second = TempFile::create(&dir.path().join("foo"), b"b", 0o644).expect("temp");
+        assert_ne!(first.path(), second.path());
+        assert!(first.path().starts_with(dir.path()));
+
+        drop(first);
+        drop(second);
+        assert!(leftover_temp_files(dir.path()).is_empty());
+    }
+
+    #[test]
+    fn persist_without_replace_keeps_existing_target() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let target = dir.path().join("demo");
+        fs::write(&target, "existing").expect("write");
+
+        let temp = TempFile::create(&target, b"new", 0o755).expect("temp");
+        let err = temp.persist(&target, false).expect_err("target exists");
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read_to_string(&target).expect("read"), "existing");
+        assert!(leftover_temp_files(dir.path()).is_empty());
+
+        let temp = TempFile::create(&target, b"new", 0o755).expect("temp");
+        temp.persist(&target, true).expect("replace");
+        assert_eq!(fs::read_to_string(&target).expect("read"), "new");
+    }
+
+    #[test]
+    fn rename_noreplace_moves_file() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let from = dir.path().join("from");
+        let to = dir.path().join("to");
+        fs::write(&from, "data").expect("write");
+
+        rename_noreplace(&from, &to).expect("rename");
+        assert!(!from.exists());
+        assert_eq!(fs::read_to_string(&to).expect("read"), "data");
+    }
+}
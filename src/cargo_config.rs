@@ -0,0 +1,619 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// A config value and the directory relative paths in it resolve against:
+/// the parent of the `.cargo` directory holding its file.
+#[derive(Debug, Clone, PartialEq)]
+struct Setting {
+    value: toml::Value,
+    dir: PathBuf,
+}
+
+/// What `cargo run` applies to the program besides building it: `[env]`
+/// and the target runner, merged from every config file cargo reads for a
+/// repository. Wrappers run the built executable directly, so they apply
+/// these themselves.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CargoConfig {
+    env: BTreeMap<String, Setting>,
+    build_target: Option<String>,
+    /// `runner` of each `[target.<triple>]` and `[target.'cfg(..)']` table.
+    runners: BTreeMap<String, Setting>,
+}
+
+/// Config files cargo reads for `repo`, lowest precedence first:
+/// `$CARGO_HOME/config.toml`, then `.cargo/config.toml` in each directory
+/// from the root down to `repo`.
+pub fn config_files(repo: &Path, cargo_home: Option<&Path>) -> Vec<PathBuf> {
+    // Cargo prefers the legacy name when both files exist.
+    let find = |dir: &Path| {
+        ["config", "config.toml"]
+            .iter()
+            .map(|name| dir.join(name))
+            .find(|path| path.is_file())
+    };
+    let mut files: Vec<PathBuf> = repo
+        .ancestors()
+        .filter_map(|dir| find(&dir.join(".cargo")))
+        .collect();
+    if let Some(file) = cargo_home.and_then(find)
+        && !files.contains(&file)
+    {
+        files.push(file);
+    }
+    files.reverse();
+    files
+}
+
+impl CargoConfig {
+    pub fn load(repo: &Path, cargo_home: Option<&Path>) -> Result<Self, String> {
+        let mut config = Self::default();
+        for file in config_files(repo, cargo_home) {
+            let contents = fs::read_to_string(&file)
+                .map_err(|err| format!("failed to read {}: {err}", file.display()))?;
+            let table: toml::Table = contents
+                .parse()
+                .map_err(|err| format!("failed to parse {}: {err}", file.display()))?;
+            let dir = file
+                .parent()
+                .and_then(Path::parent)
+                .unwrap_or(Path::new("/"));
+            config.merge(&table, dir);
+        }
+        Ok(config)
+    }
+
+    /// Adds the settings of one file over those read so far.
+    fn merge(&mut self, table: &toml::Table, dir: &Path) {
+        let setting = |value: &toml::Value| Setting {
+            value: value.clone(),
+            dir: dir.to_path_buf(),
+        };
+        if let Some(env) = table.get("env").and_then(toml::Value::as_table) {
+            for (key, value) in env {
+                self.env.insert(key.clone(), setting(value));
+            }
+        }
+        match table.get("build").and_then(|build| build.get("target")) {
+            Some(toml::Value::String(target)) => self.build_target = Some(target.clone()),
+            Some(toml::Value::Array(targets)) => {
+                if let [toml::Value::String(target)] = targets.as_slice() {
+                    self.build_target = Some(target.clone());
+                }
+            }
+            _ => {}
+        }
+        if let Some(targets) = table.get("target").and_then(toml::Value::as_table) {
+            for (key, target) in targets {
+                if let Some(runner) = target.get("runner") {
+                    self.runners.insert(key.clone(), setting(runner));
+                }
+            }
+        }
+    }
+
+    /// `[env]` as `cargo run` applies it: a variable already in `vars` is
+    /// kept unless the entry sets `force`, and `relative` values are resolved
+    /// against the config's directory.
+    pub fn env(&self, vars: &HashMap<String, String>) -> Result<Vec<(String, OsString)>, String> {
+        let mut env = Vec::new();
+        for (key, setting) in &self.env {
+            let (value, force, relative) = match &setting.value {
+                toml::Value::String(value) => (value.as_str(), false, false),
+                toml::Value::Table(table) => {
+                    let value = table
+                        .get("value")
+                        .and_then(toml::Value::as_str)
+                        .ok_or_else(|| format!("[env] {key}: `value` must be a string"))?;
+                    let flag = |name| {
+                        table
+                            .get(name)
+                            .and_then(toml::Value::as_bool)
+                            .unwrap_or(false)
+                    };
+                    (value, flag("force"), flag("relative"))
+                }
+                _ => {
+                    return Err(format!(
+                        "[env] {key}: expected a string or a table with `value`"
+                    ));
+                }
+            };
+            if !force && vars.contains_key(key) {
+                continue;
+            }
+            let value = if relative {
+                setting.dir.join(value).into_os_string()
+            } else {
+                value.into()
+            };
+            env.push((key.clone(), value));
+        }
+        Ok(env)
+    }
+
+    /// The runner `cargo run` would start the program through, as the runner
+    /// and its leading arguments. `CARGO_TARGET_<TRIPLE>_RUNNER` wins over
+    /// `target.<triple>.runner`, which wins over a matching
+    /// `target.'cfg(..)'.runner`. `rustc` answers `rustc <args>` and is only
+    /// asked when a runner could apply.
+    pub fn runner(
+        &self,
+        vars: &HashMap<String, String>,
+        mut rustc: impl FnMut(&[&str]) -> Result<String, String>,
+    ) -> Result<Option<Vec<OsString>>, String> {
+        let runner_var =
+            |key: &String| key.starts_with("CARGO_TARGET_") && key.ends_with("_RUNNER");
+        if self.runners.is_empty() && !vars.keys().any(runner_var) {
+            return Ok(None);
+        }
+
+        let target = match vars
+            .get("CARGO_BUILD_TARGET")
+            .or(self.build_target.as_ref())
+        {
+            Some(target) => target.clone(),
+            None => rustc(&["-vV"])?
+                .lines()
+                .find_map(|line| line.strip_prefix("host: "))
+                .map(str::to_string)
+                .ok_or_else(|| "rustc did not report its host target".to_string())?,
+        };
+        // A custom target is given as the path of its JSON spec.
+        let triple = match target.strip_suffix(".json") {
+            Some(path) => Path::new(path)
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            None => target.clone(),
+        };
+
+        let var = format!(
+            "CARGO_TARGET_{}_RUNNER",
+            triple.to_uppercase().replace(['-', '.'], "_")
+        );
+        if let Some(runner) = vars.get(&var) {
+            // Paths in environment variables are relative to the cwd.
+            return Ok(Some(
+                runner.split_whitespace().map(OsString::from).collect(),
+            ));
+        }
+        if let Some(setting) = self.runners.get(&triple) {
+            return runner_command(&triple, setting).map(Some);
+        }
+
+        let cfg_runners: Vec<_> = self
+            .runners
+            .iter()
+            .filter(|(key, _)| key.starts_with("cfg("))
+            .collect();
+        if cfg_runners.is_empty() {
+            return Ok(None);
+        }
+        let cfgs = parse_print_cfg(&rustc(&["--print", "cfg", "--target", &target])?);
+        let mut matching = Vec::new();
+        for (key, setting) in cfg_runners {
+            if cfg_matches(key, &cfgs)? {
+                matching.push((key, setting));
+            }
+        }
+        match matching.as_slice() {
+            [] => Ok(None),
+            [(key, setting)] => runner_command(key, setting).map(Some),
+            _ => Err(format!(
+                "several `target.'cfg(..)'.runner` entries match {triple}: {}",
+                matching
+                    .iter()
+                    .map(|(key, _)| key.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+        }
+    }
+}
+
+/// Splits a runner into the program and its arguments. A relative program
+/// path with a slash is resolved against the config's directory; a bare
+/// name is looked up on `PATH`.
+fn runner_command(key: &str, setting: &Setting) -> Result<Vec<OsString>, String> {
+    let parts: Vec<&str> = match &setting.value {
+        toml::Value::String(runner) => runner.split_whitespace().collect(),
+        toml::Value::Array(items) => items
+            .iter()
+            .map(|item| item.as_str())
+            .collect::<Option<_>>()
+            .ok_or_else(|| format!("target.{key}.runner: expected strings"))?,
+        _ => {
+            return Err(format!(
+                "target.{key}.runner: expected a string or an array of strings"
+            ));
+        }
+    };
+    let Some((program, args)) = parts.split_first() else {
+        return Err(format!("target.{key}.runner is empty"));
+    };
+    let program = if program.contains('/') {
+        setting.dir.join(program).into_os_string()
+    } else {
+        program.into()
+    };
+    Ok(std::iter::once(program)
+        .chain(args.iter().map(OsString::from))
+        .collect())
+}
+
+/// `rustc --print cfg` output as names and optional values.
+fn parse_print_cfg(output: &str) -> Vec<(String, Option<String>)> {
+    output
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| match line.split_once('=') {
+            Some((name, value)) => (name.to_string(), Some(value.trim_matches('"').to_string())),
+            None => (line.to_string(), None),
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Open,
+    Close,
+    Comma,
+    Equals,
+}
+
+fn tokenize(expr: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = expr.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            ',' => tokens.push(Token::Comma),
+            '=' => tokens.push(Token::Equals),
+            '"' => {
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => value.push(c),
+                        None => return Err("unterminated string".to_string()),
+                    }
+                }
+                tokens.push(Token::Str(value));
+            }
+            c if c.is_whitespace() => {}
+            c if c.is_alphanumeric() || c == '_' => {
+                let mut ident = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_alphanumeric() || c == '_') {
+                        break;
+                    }
+                    ident.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Ident(ident));
+            }
+            c => return Err(format!("unexpected '{c}'")),
+        }
+    }
+    Ok(tokens)
+}
+
+/// Evaluates a `cfg(..)` expression as it is being parsed.
+struct CfgParser<'a> {
+    tokens: std::vec::IntoIter<Token>,
+    peeked: Option<Token>,
+    cfgs: &'a [(String, Option<String>)],
+}
+
+impl CfgParser<'_> {
+    fn next(&mut self) -> Option<Token> {
+        self.peeked.take().or_else(|| self.tokens.next())
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        match self.next() {
+            Some(next) if next == *token => true,
+            next => {
+                self.peeked = next;
+                false
+            }
+        }
+    }
+
+    fn predicate(&mut self) -> Result<bool, String> {
+        let Some(Token::Ident(name)) = self.next() else {
+            return Err("expected a name".to_string());
+        };
+        if self.eat(&Token::Equals) {
+            let Some(Token::Str(value)) = self.next() else {
+                return Err(format!("expected a string after `{name} =`"));
+            };
+            return Ok(self.cfgs.contains(&(name, Some(value))));
+        }
+        if !self.eat(&Token::Open) {
+            return Ok(self.cfgs.contains(&(name, None)));
+        }
+
+        // A list of predicates, possibly empty and with a trailing comma.
+        let mut values = Vec::new();
+        while !self.eat(&Token::Close) {
+            values.push(self.predicate()?);
+            if self.eat(&Token::Close) {
+                break;
+            }
+            if !self.eat(&Token::Comma) {
+                return Err("expected ',' or ')'".to_string());
+            }
+        }
+        match (name.as_str(), values.as_slice()) {
+            ("all", _) => Ok(values.iter().all(|value| *value)),
+            ("any", _) => Ok(values.iter().any(|value| *value)),
+            ("not", [value]) => Ok(!value),
+            _ => Err(format!("unknown predicate `{name}(..)`")),
+        }
+    }
+}
+
+/// Whether `target.<key>` applies to a target with these cfg values.
+fn cfg_matches(key: &str, cfgs: &[(String, Option<String>)]) -> Result<bool, String> {
+    let invalid = |err: String| format!("invalid `target.'{key}'`: {err}");
+    let mut parser = CfgParser {
+        tokens: tokenize(key).map_err(invalid)?.into_iter(),
+        peeked: None,
+        cfgs,
+    };
+    let matches = match parser.next() {
+        Some(Token::Ident(name)) if name == "cfg" && parser.eat(&Token::Open) => {
+            let matches = parser.predicate().map_err(invalid)?;
+            if !parser.eat(&Token::Close) {
+                return Err(invalid("expected ')'".to_string()));
+            }
+            matches
+        }
+        _ => return Err(invalid("expected `cfg(..)`".to_string())),
+    };
+    match parser.next() {
+        None => Ok(matches),
+        Some(_) => Err(invalid("unexpected input after `cfg(..)`".to_string())),
+    }
+}
+
+/// `exe` as `cargo run` would start it for the crate at `repo`: through the
+/// configured runner and with `[env]` set. `argv0` is only applied without a
+/// runner, which receives the executable's path as its first argument.
+pub fn command(
+    exe: &Path,
+    argv0: Option<&OsStr>,
+    repo: &Path,
+    cargo_home: Option<&Path>,
+    toolchain: Option<&str>,
+) -> Result<Command, String> {
+    let vars: HashMap<String, String> = std::env::vars_os()
+        .filter_map(|(key, value)| Some((key.into_string().ok()?, value.to_string_lossy().into())))
+        .collect();
+    let cargo_home = cargo_home.map(Path::to_path_buf).or_else(|| {
+        std::env::var_os("CARGO_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cargo")))
+    });
+    let config = CargoConfig::load(repo, cargo_home.as_deref())?;
+
+    let rustc = |args: &[&str]| {
+        let mut cmd = Command::new(std::env::var_os("RUSTC").unwrap_or_else(|| "rustc".into()));
+        cmd.current_dir(repo).args(args);
+        match toolchain {
+            Some(toolchain) => cmd.env("RUSTUP_TOOLCHAIN", toolchain),
+            None => cmd.env_remove("RUSTUP_TOOLCHAIN"),
+        };
+        let output = cmd
+            .output()
+            .map_err(|err| format!("failed to run rustc: {err}"))?;
+        if !output.status.success() {
+            return Err(format!(
+                "rustc {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    };
+    let mut cmd = match config.runner(&vars, rustc)? {
+        Some(runner) => {
+            let mut cmd = Command::new(&runner[0]);
+            cmd.args(&runner[1..]).arg(exe);
+            cmd
+        }
+        None => {
+            let mut cmd = Command::new(exe);
+            if let Some(argv0) = argv0 {
+                cmd.arg0(argv0);
+            }
+            cmd
+        }
+    };
+    cmd.envs(config.env(&vars)?);
+    Ok(cmd)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOST: &str = "x86_64-unknown-linux-gnu";
+
+    fn write_file(path: &Path, contents: &str) {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).expect("create parent");
+        }
+        fs::write(path, contents).expect("write file");
+    }
+
+    fn vars(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn rustc(args: &[&str]) -> Result<String, String> {
+        match args {
+            ["-vV"] => Ok(format!("rustc 1.0.0\nhost: {HOST}\n")),
+            ["--print", "cfg", "--target", HOST] => Ok(
+                "debug_assertions\nunix\ntarget_os=\"linux\"\ntarget_arch=\"x86_64\"\n".to_string(),
+            ),
+            _ => Err(format!("unexpected rustc {args:?}")),
+        }
+    }
+
+    fn no_rustc(args: &[&str]) -> Result<String, String> {
+        panic!("rustc {args:?} should not run")
+    }
+
+    #[test]
+    fn deeper_config_files_take_precedence() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let home = dir.path().join("cargo-home");
+        let repo = dir.path().join("ws/repo");
+        write_file(
+            &home.join("config.toml"),
+            "[env]\nFROM_HOME = \"home\"\nSHARED = \"home\"\n",
+        );
+        write_file(
+            &dir.path().join("ws/.cargo/config.toml"),
+            "[env]\nSHARED = \"ws\"\nDATA = { value = \"data\", relative = true }\n",
+        );
+        write_file(
+            &repo.join(".cargo/config.toml"),
+            "[env]\nSHARED = \"repo\"\n",
+        );
+        fs::create_dir_all(repo.join("src")).expect("create src");
+
+        assert_eq!(
+            config_files(&repo.join("src"), Some(&home)),
+            [
+                home.join("config.toml"),
+                dir.path().join("ws/.cargo/config.toml"),
+                repo.join(".cargo/config.toml"),
+            ]
+        );
+        let config = CargoConfig::load(&repo, Some(&home)).expect("load");
+        let env = config.env(&HashMap::new()).expect("env");
+        assert_eq!(
+            env,
+            [
+                (
+                    "DATA".to_string(),
+                    dir.path().join("ws/data").into_os_string()
+                ),
+                ("FROM_HOME".to_string(), "home".into()),
+                ("SHARED".to_string(), "repo".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn env_keeps_existing_variables_unless_forced() {
+        let mut config = CargoConfig::default();
+        let table: toml::Table =
+            "[env]\nKEPT = \"config\"\nFORCED = { value = \"config\", force = true }\n"
+                .parse()
+                .expect("parse");
+        config.merge(&table, Path::new("/repo"));
+
+        let env = config
+            .env(&vars(&[("KEPT", "caller"), ("FORCED", "caller")]))
+            .expect("env");
+        assert_eq!(env, [("FORCED".to_string(), "config".into())]);
+    }
+
+    #[test]
+    fn runner_prefers_variable_then_triple_then_cfg() {
+        let mut config = CargoConfig::default();
+        let table: toml::Table =             "[target.'cfg(all(unix, not(windows)))']\nrunner = \"tools/run --flag\"\n\n[target.'cfg(target_os = \"macos\")']\nrunner = \"mac\"\n"
+
+        .parse()
+        .expect("parse");
+        config.merge(&table, Path::new("/repo"));
+        assert_eq!(
+            config.runner(&HashMap::new(), rustc).expect("cfg runner"),
+            Some(vec!["/repo/tools/run".into(), "--flag".into()])
+        );
+
+        let table: toml::Table = format!("[target.{HOST}]\nrunner = [\"valgrind\", \"-q\"]\n")
+            .parse()
+            .expect("parse");
+        config.merge(&table, Path::new("/repo"));
+        assert_eq!(
+            config
+                .runner(&HashMap::new(), rustc)
+                .expect("triple runner"),
+            Some(vec!["valgrind".into(), "-q".into()])
+        );
+
+        let vars = vars(&[(
+            "CARGO_TARGET_X86_64_UNKNOWN_LINUX_GNU_RUNNER",
+            "env-runner a",
+        )]);
+        assert_eq!(
+            config.runner(&vars, rustc).expect("variable runner"),
+            Some(vec!["env-runner".into(), "a".into()])
+        );
+    }
+
+    #[test]
+    fn runner_does_not_ask_rustc_without_runners() {
+        let config = CargoConfig::default();
+        assert_eq!(
+            config.runner(&HashMap::new(), no_rustc).expect("runner"),
+            None
+        );
+    }
+
+    #[test]
+    fn runner_refuses_several_matching_cfg_tables() {
+        let mut config = CargoConfig::default();
+        let table: toml::Table =
+            "[target.'cfg(unix)']\nrunner = \"a\"\n\n[target.'cfg(target_os = \"linux\")']\nrunner = \"b\"\n"
+                .parse()
+                .expect("parse");
+        config.merge(&table, Path::new("/repo"));
+        let err = config
+            .runner(&HashMap::new(), rustc)
+            .expect_err("ambiguous");
+        assert!(err.contains("several"), "{err}");
+    }
+
+    #[test]
+    fn cfg_expressions() {
+        let cfgs = parse_print_cfg("unix\ntarget_os=\"linux\"\ntarget_pointer_width=\"64\"\n");
+        for (key, expected) in [
+            ("cfg(unix)", true),
+            ("cfg(windows)", false),
+            ("cfg(target_os = \"linux\")", true),
+            ("cfg(all())", true),
+            ("cfg(any())", false),
+            ("cfg(all(unix, target_pointer_width = \"64\",))", true),
+            ("cfg(any(windows, not(unix)))", false),
+            ("cfg(not(any(windows, target_os = \"macos\")))", true),
+        ] {
+            assert_eq!(cfg_matches(key, &cfgs), Ok(expected), "{key}");
+        }
+        for key in [
+            "cfg(unix",
+            "cfg(unix))",
+            "unix",
+            "cfg(not(unix, windows))",
+            "cfg(os = linux)",
+        ] {
+            assert!(cfg_matches(key, &cfgs).is_err(), "{key}");
+        }
+    }
+}
@@ -0,0 +1,333 @@
+use crate::git;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// A clone managed by this tool for `--git` installs. It is an ordinary,
+/// editable checkout; `update` pulls into it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkout {
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    /// Revision the checkout is pinned to; pinned checkouts are not pulled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rev: Option<String>,
+    pub dir: PathBuf,
+}
+
+/// Directory holding managed clones:
+/// `$XDG_DATA_HOME/cargo-dev-install/checkouts`.
+pub fn checkouts_dir(env: &crate::EnvSnapshot) -> Option<PathBuf> {
+    crate::install::data_dir(env).map(|dir| dir.join("checkouts"))
+}
+
+/// Plain paths are resolved against `cwd` so the recorded URL keeps working
+/// from anywhere; URLs and scp-like `host:path` remotes are kept as given.
+pub fn normalize_url(url: &str, cwd: &Path) -> Result<String, String> {
+    let is_path = !url.contains("://") && !url.split('/').next().unwrap_or("").contains(':');
+    if !is_path {
+        return Ok(url.to_string());
+    }
+
+    let path = cwd.join(url);
+    path.canonicalize()
+        .map(|path| path.display().to_string())
+        .map_err(|err| format!("failed to resolve {}: {err}", path.display()))
+}
+
+/// Name of the checkout directory: the last URL component without `.git`
+/// and a hash of the whole URL, so `a/tool` and `b/tool` get their own
+/// clones.
+pub fn checkout_name(url: &str) -> Option<String> {
+    use sha2::{Digest, Sha256};
+    let name = url
+        .trim_end_matches('/')
+        .rsplit(['/', ':'])
+        .next()?
+        .trim_end_matches(".git");
+    if name.is_empty() {
+        return None;
+    }
+    let digest = Sha256::digest(url.as_bytes());
+    let hex: String = digest[..8]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    Some(format!("{name}-{hex}"))
+}
+
+/// Refuses values git would parse as options, such as
+/// `--upload-pack=<command>`.
+fn check_arg(kind: &str, value: &str) -> Result<(), String> {
+    if value.starts_with('-') {
+        return Err(format!("invalid {kind} '{value}': must not start with '-'"));
+    }
+    Ok(())
+}
+
+/// Clones `url` into the checkouts directory, or reuses an existing clone of
+/// the same URL, and checks out the requested branch or revision.
+pub fn fetch(
+    checkouts_dir: &Path,
+    url: &str,
+    branch: Option<&str>,
+    rev: Option<&str>,
+) -> Result<Checkout, String> {
+    check_arg("URL", url)?;
+    for (kind, value) in [("branch", branch), ("revision", rev)] {
+        if let Some(value) = value {
+            check_arg(kind, value)?;
+        }
+    }
+    let name =
+        checkout_name(url).ok_or_else(|| format!("cannot derive a checkout name from '{url}'"))?;
+    let checkout = Checkout {
+        url: url.to_string(),
+        branch: branch.map(str::to_string),
+        rev: rev.map(str::to_string),
+        dir: checkouts_dir.join(&name),
+    };
+
+    if checkout.dir.exists() {
+        let origin = git::run(&checkout.dir, &["remote", "get-url", "origin"])?;
+        if origin != url {
+            return Err(format!(
+                "{} is already a checkout of {origin}",
+                checkout.dir.display()
+            ));
+        }
+        git::run(&checkout.dir, &["fetch", "--quiet", "origin"])?;
+        match (&checkout.rev, &checkout.branch) {
+            (Some(rev), _) => {
+                git::run(&checkout.dir, &["checkout", "--quiet", "--detach", rev])?;
+            }
+            (None, Some(branch)) => {
+                git::run(&checkout.dir, &["checkout", "--quiet", branch])?;
+                pull(&checkout)?;
+            }
+            (None, None) => {
+                // After a `--rev` install HEAD is detached and has nothing to
+                // pull; go back to the remote's default branch first.
+                let branch = default_branch(&checkout.dir)?;
+                git::run(&checkout.dir, &["checkout", "--quiet", &branch])?;
+                pull(&checkout)?;
+            }
+        }
+        return Ok(checkout);
+    }
+
+    std::fs::create_dir_all(checkouts_dir)
+        .map_err(|err| format!("failed to create {}: {err}", checkouts_dir.display()))?;
+    let dir = checkout.dir.to_string_lossy();
+    let mut args = vec!["clone", "--quiet"];
+    if let Some(branch) = branch {
+        args.extend(["--branch", branch]);
+    }
+    args.extend(["--", url, &dir]);
+    git::run(checkouts_dir, &args)?;
+    if let Some(rev) = rev {
+        git::run(&checkout.dir, &["checkout", "--quiet", "--detach", rev])?;
+    }
+    Ok(checkout)
+}
+
+/// The branch `origin/HEAD` points at, as a local branch name.
+fn default_branch(dir: &Path) -> Result<String, String> {
+    git::run(dir, &["remote", "set-head", "origin", "--auto"])?;
+    let head = git::run(
+        dir,
+        &["symbolic-ref", "--short", "refs/remotes/origin/HEAD"],
+    )?;
+    Ok(head
+        .strip_prefix("origin/")
+        .map(str::to_string)
+        .unwrap_or(head))
+}
+
+/// Fast-forwards the checkout to its upstream; local edits are kept and a
+/// diverged checkout is reported rather than merged.
+pub fn pull(checkout: &Checkout) -> Result<(), String> {
+    git::run(&checkout.dir, &["pull", "--quiet", "--ff-only"]).map(|_| ())
+}
+
+/// Pulls the checkout, or moves it to `rev` when given, and describes the
+/// change. Checkouts pinned to a revision only move when `rev` is given.
+pub fn update(checkout: &mut Checkout, rev: Option<&str>) -> Result<String, String> {
+    if let Some(rev) = rev {
+        check_arg("revision", rev)?;
+    }
+    let previous = git::run(&checkout.dir, &["rev-parse", "--short=12", "HEAD"])?;
+    git::run(&checkout.dir, &["fetch", "--quiet", "origin"])?;
+    match (rev, &checkout.rev) {
+        (Some(rev), _) => {
+            git::run(&checkout.dir, &["checkout", "--quiet", "--detach", rev])?;
+            checkout.rev = Some(rev.to_string());
+        }
+        (None, Some(pinned)) => return Ok(format!("pinned to {pinned}")),
+        (None, None) => pull(checkout)?,
+    }
+
+    let current = git::run(&checkout.dir, &["rev-parse", "--short=12", "HEAD"])?;
+    Ok(if previous == current {
+        format!("already at {current}")
+    } else {
+        format!("updated {previous} -> {current}")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::process::{Command, Stdio};
+
+    fn run_git(dir: &Path, args: &[&str]) {
+        let status = Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .expect("run git");
+        assert!(status.success(), "git {args:?}");
+    }
+
+    fn commit_file(repo: &Path, contents: &str) {
+        fs::write(repo.join("file.txt"), contents).expect("write file");
+        run_git(repo, &["add", "-A"]);
+        run_git(repo, &["commit", "--quiet", "-m", contents]);
+    }
+
+    #[test]
+    fn checkout_name_uses_last_url_component_and_url_hash() {
+        for url in [
+            "https://example.com/org/tool.git",
+            "file:///srv/git/tool/",
+            "git@example.com:tool.git",
+            "../tool",
+        ] {
+            let name = checkout_name(url).expect("name");
+            assert!(name.starts_with("tool-"), "{name}");
+            assert_eq!(name.len(), "tool-".len() + 16, "{name}");
+        }
+        assert_ne!(
+            checkout_name("https://example.com/a/tool"),
+            checkout_name("https://example.com/b/tool")
+        );
+        assert_eq!(checkout_name("https://example.com/.git"), None);
+    }
+
+    #[test]
+    fn normalize_url_resolves_plain_paths_only() {
+        let dir = tempfile::tempdir().expect("dir");
+        fs::create_dir(dir.path().join("tool")).expect("create");
+        let resolved = normalize_url("tool", dir.path()).expect("normalize");
+        assert_eq!(
+            PathBuf::from(resolved),
+            dir.path().join("tool").canonicalize().unwrap()
+        );
+
+        assert_eq!(
+            normalize_url("file:///srv/tool", dir.path()).unwrap(),
+            "file:///srv/tool"
+        );
+        assert_eq!(
+            normalize_url("git@example.com:tool", dir.path()).unwrap(),
+            "git@example.com:tool"
+        );
+        assert!(normalize_url("missing", dir.path()).is_err());
+    }
+
+    #[test]
+    fn fetch_clones_then_pulls_existing_checkout() {
+        let upstream = tempfile::tempdir().expect("upstream");
+        run_git(
+            upstream.path(),
+            &["init", "--quiet", "--initial-branch=main"],
+        );
+        commit_file(upstream.path(), "one");
+        run_git(upstream.path(), &["branch", "stable"]);
+
+        let data = tempfile::tempdir().expect("data");
+        let url = format!("file://{}", upstream.path().display());
+        let checkout = fetch(data.path(), &url, None, None).expect("clone");
+        assert_eq!(checkout.dir, data.path().join(checkout_name(&url).unwrap()));
+        assert_eq!(
+            fs::read_to_string(checkout.dir.join("file.txt")).unwrap(),
+            "one"
+        );
+
+        commit_file(upstream.path(), "two");
+        fetch(data.path(), &url, None, None).expect("reuse");
+        assert_eq!(
+            fs::read_to_string(checkout.dir.join("file.txt")).unwrap(),
+            "two"
+        );
+
+        fetch(data.path(), &url, Some("stable"), None).expect("branch");
+        assert_eq!(
+            fs::read_to_string(checkout.dir.join("file.txt")).unwrap(),
+            "one"
+        );
+
+        // A clone of another URL never lands in this checkout.
+        let other = format!("{url}/");
+        let other_checkout = fetch(data.path(), &other, None, None).expect("other clone");
+        assert_ne!(other_checkout.dir, checkout.dir);
+
+        run_git(
+            &checkout.dir,
+            &["remote", "set-url", "origin", "file:///elsewhere/tool"],
+        );
+        let err = fetch(data.path(), &url, None, None).expect_err("foreign checkout");
+        assert!(err.contains("is already a checkout of"));
+    }
+
+    #[test]
+    fn fetch_refuses_arguments_git_would_take_as_options() {
+        let data = tempfile::tempdir().expect("data");
+        let err = fetch(data.path(), "--upload-pack=touch pwned:x", None, None)
+            .expect_err("option as URL");
+        assert!(err.contains("must not start with '-'"), "{err}");
+        let err = fetch(data.path(), "file:///srv/tool", Some("--orphan"), None)
+            .expect_err("option as branch");
+        assert!(err.contains("invalid branch"), "{err}");
+        let err = fetch(data.path(), "file:///srv/tool", None, Some("-b"))
+            .expect_err("option as revision");
+        assert!(err.contains("invalid revision"), "{err}");
+        assert_eq!(fs::read_dir(data.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn fetch_without_rev_leaves_a_pinned_checkout() {
+        let upstream = tempfile::tempdir().expect("upstream");
+        run_git(
+            upstream.path(),
+            &["init", "--quiet", "--initial-branch=main"],
+        );
+        commit_file(upstream.path(), "one");
+        let first = git::run(upstream.path(), &["rev-parse", "HEAD"]).expect("rev");
+        commit_file(upstream.path(), "two");
+
+        let data = tempfile::tempdir().expect("data");
+        let url = format!("file://{}", upstream.path().display());
+        let checkout = fetch(data.path(), &url, None, Some(&first)).expect("pinned clone");
+        assert_eq!(
+            fs::read_to_string(checkout.dir.join("file.txt")).unwrap(),
+            "one"
+        );
+
+        commit_file(upstream.path(), "three");
+        fetch(data.path(), &url, None, None).expect("unpinned fetch");
+        assert_eq!(
+            fs::read_to_string(checkout.dir.join("file.txt")).unwrap(),
+            "three"
+        );
+        assert_eq!(
+            git::run(&checkout.dir, &["branch", "--show-current"]).unwrap(),
+            "main"
+        );
+    }
+}
@@ -1,18 +1,179 @@
-use clap::Parser;
+use crate::install::{BuildOutput, WrapperStyle};
+use clap::{Parser, Subcommand};
+use std::ffi::OsString;
+use std::path::PathBuf;
 
 #[derive(Debug, Parser)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, args_conflicts_with_subcommands = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
     #[arg(long)]
     bin: Option<String>,
+    /// Install the wrapper as NAME instead of the binary's name, e.g. a snapshot next to a live wrapper
+    #[arg(long, value_name = "NAME")]
+    name: Option<String>,
     #[arg(long)]
     force: bool,
+    /// Also replace files not installed by this tool, keeping a backup for `uninstall`
+    #[arg(long)]
+    force_foreign: bool,
+    /// Install even into a world-writable or foreign-owned directory, or from a repository owned by another user
+    #[arg(long)]
+    allow_unsafe_paths: bool,
+    /// Pin the build to this toolchain; otherwise rustup reads the repository's rust-toolchain file at run time
+    #[arg(long)]
+    toolchain: Option<String>,
+    /// Unset inherited cargo build variables before building (default for cargo-* bins)
+    #[arg(long, conflicts_with = "no_sanitize_env")]
+    sanitize_env: bool,
+    #[arg(long)]
+    no_sanitize_env: bool,
+    /// Build into a per-wrapper target dir under $XDG_CACHE_HOME/cargo-dev-install/targets
+    #[arg(long)]
+    isolated_target_dir: bool,
+    #[arg(long, value_enum, default_value_t = WrapperStyle::Bash)]
+    wrapper_style: WrapperStyle,
+    /// How build output is shown; CARGO_DEV_INSTALL_BUILD_OUTPUT overrides it at run time
+    #[arg(long, value_enum, default_value_t = BuildOutput::Cargo)]
+    build_output: BuildOutput,
+    /// Run the last successful build when the working tree does not compile
+    #[arg(long)]
+    fallback_last_good: bool,
+    /// With --fallback-last-good, still exit non-zero when the build failed
+    #[arg(long, requires = "fallback_last_good")]
+    strict: bool,
+    /// Build before installing and fail if the crate does not compile
+    #[arg(long)]
+    build: bool,
+    /// Run the installed wrapper once with these arguments, split like a shell does, and require exit 0
+    #[arg(long, value_name = "ARGS", allow_hyphen_values = true)]
+    verify_args: Option<String>,
+    /// Build from a managed worktree pinned to REV (default HEAD) instead of the checkout
+    #[arg(long, value_name = "REV", num_args = 0..=1, default_missing_value = "HEAD")]
+    snapshot: Option<String>,
+    /// Clone this repository (URL or path) into a managed checkout and install from it
+    #[arg(long, value_name = "URL", conflicts_with = "snapshot")]
+    git: Option<String>,
+    /// With --git, check out this branch
+    #[arg(long, requires = "git", conflicts_with = "rev")]
+    branch: Option<String>,
+    /// With --git, pin the checkout to this revision
+    #[arg(long, requires = "git")]
+    rev: Option<String>,
+    /// Install into the workspace's .bin (or its configured local-bin-dir) instead of the global bin dir
+    #[arg(long, conflicts_with = "git")]
+    local: bool,
+    /// With --local, add a `PATH_add` line for the local bin dir to the workspace's .envrc
+    #[arg(long, requires = "local")]
+    envrc: bool,
+    /// Run the program with argv[0] set to the invoked name (bash and native wrappers only)
+    #[arg(long)]
+    preserve_argv0: bool,
+    /// Also install NAME as a link to the wrapper, for multi-call binaries; implies --preserve-argv0
+    #[arg(long = "alias", value_name = "NAME")]
+    aliases: Vec<String>,
+    /// Export the repository context to the program as PREFIX_* variables (default CARGO_DEV_INSTALL or the project's env-prefix)
+    #[arg(long, value_name = "PREFIX", num_args = 0..=1, require_equals = true)]
+    export_context: Option<Option<String>>,
+}
+
+/// Commands besides the default install.
+#[derive(Debug, Clone, PartialEq, Eq, Subcommand)]
+pub enum Command {
+    /// Rebuild registered wrappers in the background whenever their sources change
+    Watch,
+    /// Move snapshot and --git wrappers to the latest commit and rebuild them
+    Update {
+        /// Only update the wrapper with this name
+        name: Option<String>,
+        /// Switch to this revision instead of re-resolving the recorded one
+        #[arg(long)]
+        rev: Option<String>,
+    },
+    /// Remove a wrapper and restore the file it replaced, if any
+    Uninstall {
+        /// Wrapper name, or its path when several wrappers share the name
+        wrapper: String,
+        /// Remove the wrapper even if it was modified since it was installed
+        #[arg(long)]
+        force: bool,
+    },
+    /// Point wrappers at a checkout that moved
+    Relink(RelinkArgs),
+    /// Regenerate wrappers written by an older version of this tool
+    Refresh {
+        /// Also re-render wrappers already on the current template
+        #[arg(long)]
+        all: bool,
+        /// Overwrite wrappers that were edited by hand
+        #[arg(long)]
+        force: bool,
+    },
+    /// Check installed wrappers against the hashes recorded when they were written
+    Verify,
+    /// Run `verify` and check the install directory and registered crates
+    Doctor,
+    /// Run a built program with the repository's `[env]` and runner (used by script wrappers)
+    #[command(hide = true)]
+    ExecProgram(ExecProgramArgs),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, clap::Args)]
+pub struct ExecProgramArgs {
+    /// Crate root whose cargo config applies
+    #[arg(long)]
+    pub repo: PathBuf,
+    #[arg(long)]
+    pub cargo_home: Option<PathBuf>,
+    #[arg(long)]
+    pub toolchain: Option<String>,
+    /// argv[0] for the program when no runner is configured
+    #[arg(long)]
+    pub argv0: Option<OsString>,
+    /// The executable and its arguments
+    #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+    pub command: Vec<OsString>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, clap::Args)]
+pub struct RelinkArgs {
+    /// Path prefix the crates used to live under
+    #[arg(required_unless_present = "scan", requires = "new_prefix")]
+    pub old_prefix: Option<PathBuf>,
+    /// Path prefix they live under now
+    pub new_prefix: Option<PathBuf>,
+    /// Find crates whose checkout disappeared under DIR by package name
+    #[arg(long, value_name = "DIR", conflicts_with = "old_prefix")]
+    pub scan: Option<PathBuf>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct CliArgs {
+    pub command: Option<Command>,
     pub bin: Option<String>,
+    pub name: Option<String>,
     pub force: bool,
+    pub force_foreign: bool,
+    pub allow_unsafe_paths: bool,
+    pub toolchain: Option<String>,
+    pub sanitize_env: Option<bool>,
+    pub isolated_target_dir: bool,
+    pub wrapper_style: WrapperStyle,
+    pub build_output: BuildOutput,
+    pub fallback_last_good: bool,
+    pub strict: bool,
+    pub build: bool,
+    pub verify_args: Option<String>,
+    pub snapshot: Option<String>,
+    pub git: Option<String>,
+    pub branch: Option<String>,
+    pub rev: Option<String>,
+    pub local: bool,
+    pub envrc: bool,
+    pub preserve_argv0: bool,
+    pub aliases: Vec<String>,
+    pub export_context: Option<Option<String>>,
 }
 
 pub fn parse_args<I, T>(args: I) -> Result<CliArgs, clap::Error>
@@ -42,8 +203,34 @@ where
 
     let parsed = Args::try_parse_from(argv)?;
     Ok(CliArgs {
+        command: parsed.command,
         bin: parsed.bin,
+        name: parsed.name,
         force: parsed.force,
+        force_foreign: parsed.force_foreign,
+        allow_unsafe_paths: parsed.allow_unsafe_paths,
+        toolchain: parsed.toolchain,
+        sanitize_env: match (parsed.sanitize_env, parsed.no_sanitize_env) {
+            (true, _) => Some(true),
+            (_, true) => Some(false),
+            _ => None,
+        },
+        isolated_target_dir: parsed.isolated_target_dir,
+        wrapper_style: parsed.wrapper_style,
+        build_output: parsed.build_output,
+        fallback_last_good: parsed.fallback_last_good,
+        strict: parsed.strict,
+        build: parsed.build,
+        verify_args: parsed.verify_args,
+        snapshot: parsed.snapshot,
+        git: parsed.git,
+        branch: parsed.branch,
+        rev: parsed.rev,
+        local: parsed.local,
+        envrc: parsed.envrc,
+        preserve_argv0: parsed.preserve_argv0,
+        aliases: parsed.aliases,
+        export_context: parsed.export_context,
     })
 }
 
@@ -57,8 +244,30 @@ mod tests {
         assert_eq!(
             args,
             CliArgs {
+                command: None,
                 bin: None,
+                name: None,
                 force: false,
+                force_foreign: false,
+                allow_unsafe_paths: false,
+                toolchain: None,
+                sanitize_env: None,
+                isolated_target_dir: false,
+                wrapper_style: WrapperStyle::Bash,
+                build_output: BuildOutput::Cargo,
+                fallback_last_good: false,
+                strict: false,
+                build: false,
+                verify_args: None,
+                snapshot: None,
+                git: None,
+                branch: None,
+                rev: None,
+                local: false,
+                envrc: false,
+                preserve_argv0: false,
+                aliases: Vec::new(),
+                export_context: None,
             }
         );
     }
@@ -70,8 +279,30 @@ mod tests {
         assert_eq!(
             args,
             CliArgs {
+                command: None,
                 bin: Some("demo".to_string()),
+                name: None,
                 force: true,
+                force_foreign: false,
+                allow_unsafe_paths: false,
+                toolchain: None,
+                sanitize_env: None,
+                isolated_target_dir: false,
+                wrapper_style: WrapperStyle::Bash,
+                build_output: BuildOutput::Cargo,
+                fallback_last_good: false,
+                strict: false,
+                build: false,
+                verify_args: None,
+                snapshot: None,
+                git: None,
+                branch: None,
+                rev: None,
+                local: false,
+                envrc: false,
+                preserve_argv0: false,
+                aliases: Vec::new(),
+                export_context: None,
             }
         );
     }
@@ -82,12 +313,305 @@ mod tests {
         assert_eq!(
             args,
             CliArgs {
+                command: None,
                 bin: None,
+                name: None,
                 force: true,
+                force_foreign: false,
+                allow_unsafe_paths: false,
+                toolchain: None,
+                sanitize_env: None,
+                isolated_target_dir: false,
+                wrapper_style: WrapperStyle::Bash,
+                build_output: BuildOutput::Cargo,
+                fallback_last_good: false,
+                strict: false,
+                build: false,
+                verify_args: None,
+                snapshot: None,
+                git: None,
+                branch: None,
+                rev: None,
+                local: false,
+                envrc: false,
+                preserve_argv0: false,
+                aliases: Vec::new(),
+                export_context: None,
             }
         );
     }
 
+    #[test]
+    fn parses_toolchain() {
+        let args = parse_args(["cargo-dev-install", "--toolchain", "nightly"]).expect("parse args");
+        assert_eq!(args.toolchain, Some("nightly".to_string()));
+    }
+
+    #[test]
+    fn parses_export_context_with_optional_prefix() {
+        let args = parse_args(["cargo-dev-install", "--export-context"]).expect("parse args");
+        assert_eq!(args.export_context, Some(None));
+
+        let args =
+            parse_args(["cargo-dev-install", "--export-context=MY_TOOL"]).expect("parse args");
+        assert_eq!(args.export_context, Some(Some("MY_TOOL".to_string())));
+    }
+
+    #[test]
+    fn parses_sanitize_env_flags() {
+        let args = parse_args(["cargo-dev-install", "--sanitize-env"]).expect("parse args");
+        assert_eq!(args.sanitize_env, Some(true));
+
+        let args = parse_args(["cargo-dev-install", "--no-sanitize-env"]).expect("parse args");
+        assert_eq!(args.sanitize_env, Some(false));
+
+        let err = parse_args(["cargo-dev-install", "--sanitize-env", "--no-sanitize-env"])
+            .expect_err("expected conflict");
+        assert_eq!(err.kind(), clap::error::ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn parses_isolated_target_dir() {
+        let args = parse_args(["cargo-dev-install", "--isolated-target-dir"]).expect("parse args");
+        assert!(args.isolated_target_dir);
+    }
+
+    #[test]
+    fn parses_wrapper_style() {
+        let args =
+            parse_args(["cargo-dev-install", "--wrapper-style", "native"]).expect("parse args");
+        assert_eq!(args.wrapper_style, WrapperStyle::Native);
+
+        let args = parse_args(["cargo-dev-install", "--wrapper-style", "sh"]).expect("parse args");
+        assert_eq!(args.wrapper_style, WrapperStyle::Sh);
+
+        let err = parse_args(["cargo-dev-install", "--wrapper-style", "fish"])
+            .expect_err("expected invalid value");
+        assert_eq!(err.kind(), clap::error::ErrorKind::InvalidValue);
+    }
+
+    #[test]
+    fn parses_build_output() {
+        let args =
+            parse_args(["cargo-dev-install", "--build-output", "quiet"]).expect("parse args");
+        assert_eq!(args.build_output, BuildOutput::Quiet);
+    }
+
+    #[test]
+    fn parses_fallback_last_good_and_strict() {
+        let args = parse_args(["cargo-dev-install", "--fallback-last-good", "--strict"])
+            .expect("parse args");
+        assert!(args.fallback_last_good);
+        assert!(args.strict);
+
+        let err = parse_args(["cargo-dev-install", "--strict"]).expect_err("expected error");
+        assert_eq!(err.kind(), clap::error::ErrorKind::MissingRequiredArgument);
+    }
+
+    #[test]
+    fn parses_build_and_hyphenated_verify_args() {
+        let args = parse_args(["cargo-dev-install", "--build", "--verify-args", "--version"])
+            .expect("parse args");
+        assert!(args.build);
+        assert_eq!(args.verify_args, Some("--version".to_string()));
+    }
+
+    #[test]
+    fn parses_watch_subcommand() {
+        let args = parse_args(["cargo", "dev-install", "watch"]).expect("parse args");
+        assert_eq!(args.command, Some(Command::Watch));
+
+        let err = parse_args(["cargo-dev-install", "--force", "watch"])
+            .expect_err("install flags conflict with subcommands");
+        assert_eq!(err.kind(), clap::error::ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn parses_exec_program_passing_program_flags_through() {
+        let args = parse_args([
+            "cargo-dev-install",
+            "exec-program",
+            "--repo",
+            "/repo",
+            "--",
+            "/repo/target/release/demo",
+            "--repo",
+            "-v",
+        ])
+        .expect("parse args");
+        assert_eq!(
+            args.command,
+            Some(Command::ExecProgram(ExecProgramArgs {
+                repo: PathBuf::from("/repo"),
+                cargo_home: None,
+                toolchain: None,
+                argv0: None,
+                command: vec![
+                    "/repo/target/release/demo".into(),
+                    "--repo".into(),
+                    "-v".into()
+                ],
+            }))
+        );
+    }
+
+    #[test]
+    fn parses_repeated_aliases() {
+        let args = parse_args(["cargo-dev-install", "--alias", "ls", "--alias", "cat"])
+            .expect("parse args");
+        assert_eq!(args.aliases, ["ls", "cat"]);
+        assert!(!args.preserve_argv0);
+    }
+
+    #[test]
+    fn parses_local_with_envrc() {
+        let args = parse_args(["cargo-dev-install", "--local", "--envrc"]).expect("parse args");
+        assert!(args.local);
+        assert!(args.envrc);
+
+        assert!(parse_args(["cargo-dev-install", "--envrc"]).is_err());
+        assert!(parse_args(["cargo-dev-install", "--local", "--git", "url"]).is_err());
+    }
+
+    #[test]
+    fn parses_snapshot_with_optional_rev() {
+        let args = parse_args(["cargo-dev-install", "--snapshot"]).expect("parse args");
+        assert_eq!(args.snapshot, Some("HEAD".to_string()));
+
+        let args =
+            parse_args(["cargo-dev-install", "--snapshot", "v1.2", "--force"]).expect("parse args");
+        assert_eq!(args.snapshot, Some("v1.2".to_string()));
+        assert!(args.force);
+    }
+
+    #[test]
+    fn parses_git_source_flags() {
+        let args = parse_args([
+            "cargo-dev-install",
+            "--git",
+            "../tool",
+            "--branch",
+            "stable",
+        ])
+        .expect("parse args");
+        assert_eq!(args.git, Some("../tool".to_string()));
+        assert_eq!(args.branch, Some("stable".to_string()));
+
+        let err = parse_args(["cargo-dev-install", "--rev", "v1"]).expect_err("requires --git");
+        assert_eq!(err.kind(), clap::error::ErrorKind::MissingRequiredArgument);
+
+        let err = parse_args([
+            "cargo-dev-install",
+            "--git",
+            "../tool",
+            "--branch",
+            "main",
+            "--rev",
+            "v1",
+        ])
+        .expect_err("branch and rev conflict");
+        assert_eq!(err.kind(), clap::error::ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn parses_update_subcommand() {
+        let args = parse_args(["cargo", "dev-install", "update"]).expect("parse args");
+        assert_eq!(
+            args.command,
+            Some(Command::Update {
+                name: None,
+                rev: None
+            })
+        );
+
+        let args = parse_args(["cargo-dev-install", "update", "demo", "--rev", "main"])
+            .expect("parse args");
+        assert_eq!(
+            args.command,
+            Some(Command::Update {
+                name: Some("demo".to_string()),
+                rev: Some("main".to_string())
+            })
+        );
+    }
+
+    #[test]
+    fn parses_relink_subcommand() {
+        let args =
+            parse_args(["cargo-dev-install", "relink", "/src", "/work"]).expect("parse args");
+        assert_eq!(
+            args.command,
+            Some(Command::Relink(RelinkArgs {
+                old_prefix: Some(PathBuf::from("/src")),
+                new_prefix: Some(PathBuf::from("/work")),
+                scan: None,
+            }))
+        );
+
+        let args =
+            parse_args(["cargo-dev-install", "relink", "--scan", "/work"]).expect("parse args");
+        assert_eq!(
+            args.command,
+            Some(Command::Relink(RelinkArgs {
+                old_prefix: None,
+                new_prefix: None,
+                scan: Some(PathBuf::from("/work")),
+            }))
+        );
+
+        for argv in [
+            vec!["cargo-dev-install", "relink"],
+            vec!["cargo-dev-install", "relink", "/src"],
+            vec![
+                "cargo-dev-install",
+                "relink",
+                "/src",
+                "/work",
+                "--scan",
+                "/x",
+            ],
+        ] {
+            parse_args(argv).expect_err("invalid relink arguments");
+        }
+    }
+
+    #[test]
+    fn parses_refresh_subcommand() {
+        let args = parse_args(["cargo", "dev-install", "refresh", "--all", "--force"])
+            .expect("parse args");
+        assert_eq!(
+            args.command,
+            Some(Command::Refresh {
+                all: true,
+                force: true
+            })
+        );
+        assert!(!args.force);
+    }
+
+    #[test]
+    fn parses_force_foreign_and_uninstall() {
+        let args = parse_args(["cargo-dev-install", "--force-foreign"]).expect("parse args");
+        assert!(args.force_foreign);
+        assert!(!args.force);
+
+        let args = parse_args(["cargo", "dev-install", "uninstall", "demo"]).expect("parse args");
+        assert_eq!(
+            args.command,
+            Some(Command::Uninstall {
+                wrapper: "demo".to_string(),
+                force: false,
+            })
+        );
+
+        let args = parse_args(["cargo", "dev-install", "uninstall", "demo", "--force"])
+            .expect("parse args");
+        assert!(matches!(
+            args.command,
+            Some(Command::Uninstall { force: true, .. })
+        ));
+    }
+
     #[test]
     fn errors_on_unknown_flag() {
         let err = parse_args(["cargo-dev-install", "--unknown"])
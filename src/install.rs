@@ -1,4 +1,5 @@
-use std::os::unix::fs::PermissionsExt;
+use crate::atomic::TempFile;
+use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
 use std::{fs, io};
 
@@ -11,57 +12,908 @@ pub fn install_dir(env: &crate::EnvSnapshot) -> Option<PathBuf> {
     Some(home.join(".local").join("bin"))
 }
 
-pub fn is_on_path(dir: &Path, path_var: Option<&str>) -> bool {
-    let path_var = match path_var {
-        Some(path_var) => path_var,
-        None => return false,
+/// Picks the cargo executable baked into wrappers. The rustup proxy is
+/// preferred over `$CARGO`, which points into one specific toolchain and
+/// would bypass the toolchain selection done by rustup.
+pub fn cargo_path(env: &crate::EnvSnapshot) -> Option<PathBuf> {
+    let cargo_home = env
+        .cargo_home
+        .clone()
+        .or_else(|| env.home.as_ref().map(|home| home.join(".cargo")));
+    if let Some(proxy) = cargo_home.map(|dir| dir.join("bin").join("cargo"))
+        && proxy.is_file()
+    {
+        return Some(proxy);
+    }
+
+    env.cargo.clone().filter(|cargo| cargo.is_absolute())
+}
+
+/// Per-user cache directory of this tool: `$XDG_CACHE_HOME/cargo-dev-install`,
+/// falling back to `$HOME/.cache/cargo-dev-install`.
+pub fn cache_dir(env: &crate::EnvSnapshot) -> Option<PathBuf> {
+    let base = match env.xdg_cache_home.as_deref() {
+        Some(xdg) => xdg.to_path_buf(),
+        None => env.home.as_deref()?.join(".cache"),
     };
+    Some(base.join("cargo-dev-install"))
+}
 
-    std::env::split_paths(path_var).any(|entry| entry == dir)
+/// Per-user data directory of this tool: `$XDG_DATA_HOME/cargo-dev-install`,
+/// falling back to `$HOME/.local/share/cargo-dev-install`.
+pub fn data_dir(env: &crate::EnvSnapshot) -> Option<PathBuf> {
+    let base = match env.xdg_data_home.as_deref() {
+        Some(xdg) => xdg.to_path_buf(),
+        None => env.home.as_deref()?.join(".local").join("share"),
+    };
+    Some(base.join("cargo-dev-install"))
 }
 
-pub fn render_wrapper(crate_root: &Path) -> String {
+/// How the install directory appears on `PATH`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathStatus {
+    Present,
+    /// Only through a relative entry (an empty entry means the current
+    /// directory), which finds the wrapper only from where it was run.
+    Relative(String),
+    /// Only through an entry starting with `~`, which bash expands during
+    /// command lookup but other shells and `execvp` do not.
+    Tilde(String),
+    Missing,
+}
+
+impl PathStatus {
+    /// Why the install directory does not reliably resolve through `PATH`.
+    pub fn problem(&self) -> Option<String> {
+        match self {
+            PathStatus::Present => None,
+            PathStatus::Relative(entry) if entry.is_empty() => Some(
+                "install directory is on PATH only through an empty entry, which means the current directory".to_string(),
+            ),
+            PathStatus::Relative(entry) => Some(format!(
+                "install directory is on PATH only through the relative entry '{entry}', which only finds it from here"
+            )),
+            PathStatus::Tilde(entry) => Some(format!(
+                "install directory is on PATH only as '{entry}', which only bash expands"
+            )),
+            PathStatus::Missing => Some("install directory is not on PATH".to_string()),
+        }
+    }
+}
+
+/// Removes `.` components, duplicate and trailing separators, and resolves
+/// `..` textually.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            std::path::Component::CurDir => {}
+            std::path::Component::ParentDir => {
+                if !normalized.pop() {
+                    normalized.push("..");
+                }
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+fn same_dir(a: &Path, b: &Path) -> bool {
+    if normalize(a) == normalize(b) {
+        return true;
+    }
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Looks for `dir` in `path_var`, treating spellings of the same directory
+/// (trailing or doubled slashes, symlinks) as equal. Relative and `~` entries
+/// are resolved against `cwd` and `home` but reported separately, since they
+/// do not reliably put the directory on `PATH`.
+pub fn is_on_path(
+    dir: &Path,
+    path_var: Option<&str>,
+    home: Option<&Path>,
+    cwd: Option<&Path>,
+) -> PathStatus {
+    let Some(path_var) = path_var else {
+        return PathStatus::Missing;
+    };
+
+    let mut fallback = PathStatus::Missing;
+    for entry in path_var.split(':') {
+        if entry.starts_with('/') {
+            if same_dir(Path::new(entry), dir) {
+                return PathStatus::Present;
+            }
+            continue;
+        }
+        if fallback != PathStatus::Missing {
+            continue;
+        }
+
+        let tilde = entry
+            .strip_prefix('~')
+            .filter(|rest| rest.is_empty() || rest.starts_with('/'));
+        if let Some(rest) = tilde {
+            if home.is_some_and(|home| same_dir(&home.join(rest.trim_start_matches('/')), dir)) {
+                fallback = PathStatus::Tilde(entry.to_string());
+            }
+        } else if cwd.is_some_and(|cwd| same_dir(&cwd.join(entry), dir)) {
+            fallback = PathStatus::Relative(entry.to_string());
+        }
+    }
+    fallback
+}
+
+/// Variables a parent cargo process exports that would leak into the nested
+/// build. They are unset for the build only; the program still sees them.
+pub const SANITIZED_ENV_VARS: &[&str] = &[
+    "CARGO",
+    "CARGO_MANIFEST_DIR",
+    "CARGO_MANIFEST_PATH",
+    "CARGO_MANIFEST_LINKS",
+    "CARGO_PKG_NAME",
+    "CARGO_PKG_VERSION",
+    "CARGO_PKG_VERSION_MAJOR",
+    "CARGO_PKG_VERSION_MINOR",
+    "CARGO_PKG_VERSION_PATCH",
+    "CARGO_PKG_VERSION_PRE",
+    "CARGO_PKG_AUTHORS",
+    "CARGO_PKG_DESCRIPTION",
+    "CARGO_PKG_HOMEPAGE",
+    "CARGO_PKG_REPOSITORY",
+    "CARGO_PKG_LICENSE",
+    "CARGO_PKG_LICENSE_FILE",
+    "CARGO_PKG_README",
+    "CARGO_PKG_RUST_VERSION",
+    "CARGO_CRATE_NAME",
+    "CARGO_BIN_NAME",
+    "CARGO_PRIMARY_PACKAGE",
+    "CARGO_TARGET_DIR",
+    "CARGO_BUILD_TARGET_DIR",
+    "CARGO_MAKEFLAGS",
+    "CARGO_ENCODED_RUSTFLAGS",
+    "CARGO_ENCODED_RUSTDOCFLAGS",
+    "RUSTFLAGS",
+    "RUSTDOCFLAGS",
+    "RUSTC",
+    "RUSTC_WRAPPER",
+    "RUSTC_WORKSPACE_WRAPPER",
+    "RUSTDOC",
+    "RUSTUP_TOOLCHAIN",
+    "OUT_DIR",
+    "TARGET",
+    "HOST",
+    "NUM_JOBS",
+    "OPT_LEVEL",
+    "DEBUG",
+    "PROFILE",
+];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum WrapperStyle {
+    /// Bash script that builds and execs the program.
+    #[default]
+    Bash,
+    /// Strictly POSIX `sh` script for systems without bash.
+    Sh,
+    /// Symlink to `cargo-dev-install`, which acts as the launcher.
+    Native,
+}
+
+/// Overrides [`WrapperOptions::build_output`] when set at run time.
+pub const BUILD_OUTPUT_ENV: &str = "CARGO_DEV_INSTALL_BUILD_OUTPUT";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum BuildOutput {
+    /// Pass cargo's stderr through, including warnings.
+    #[default]
+    Cargo,
+    /// Capture cargo's output; show a progress line on a TTY and the full
+    /// diagnostics only if the build fails.
+    Quiet,
+}
+
+impl BuildOutput {
+    pub fn name(self) -> &'static str {
+        match self {
+            BuildOutput::Cargo => "cargo",
+            BuildOutput::Quiet => "quiet",
+        }
+    }
+
+    /// The mode in effect at run time: a valid [`BUILD_OUTPUT_ENV`] value
+    /// wins over the one chosen at install time.
+    pub fn resolve(self, env_value: Option<&str>) -> Self {
+        match env_value {
+            Some("cargo") => BuildOutput::Cargo,
+            Some("quiet") => BuildOutput::Quiet,
+            _ => self,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WrapperOptions {
+    pub style: WrapperStyle,
+    /// Toolchain passed to rustup via `RUSTUP_TOOLCHAIN` for the build.
+    pub toolchain: Option<String>,
+    /// Absolute cargo executable; looked up on `PATH` at run time if missing.
+    pub cargo: Option<PathBuf>,
+    pub cargo_home: Option<PathBuf>,
+    pub rustup_home: Option<PathBuf>,
+    /// Unset [`SANITIZED_ENV_VARS`] before building.
+    pub sanitize_env: bool,
+    /// Dedicated `CARGO_TARGET_DIR` for this wrapper's builds.
+    pub target_dir: Option<PathBuf>,
+    /// Advisory lock serializing concurrent builds of this wrapper.
+    pub lock_path: Option<PathBuf>,
+    pub build_output: BuildOutput,
+    /// Copy of the last successful build, run when a rebuild fails.
+    pub last_good: Option<PathBuf>,
+    /// Exit with the build's failure status even after running `last_good`.
+    pub strict: bool,
+    /// Run the program with `argv[0]` set to the name the wrapper was invoked
+    /// as instead of the path of the built executable. Not possible in POSIX
+    /// sh, which has no `exec -a`.
+    pub preserve_argv0: bool,
+    /// Prefix of the variables describing the repository that are exported
+    /// to the program, such as `<prefix>_REPO_ROOT`; none are exported
+    /// without one.
+    pub env_prefix: Option<String>,
+    /// Root of the repository the crate lives in, exported as
+    /// `<prefix>_REPO_ROOT`; the crate root if unset.
+    pub repo_root: Option<PathBuf>,
+    /// `cargo-dev-install` executable script wrappers start the program
+    /// through, so it gets the repository's `[env]` and runner like under
+    /// `cargo run`; the program is exec'd directly if it is gone.
+    pub launcher: Option<PathBuf>,
+}
+
+/// Prefix used for the exported repository context unless the project or
+/// `--export-context=<PREFIX>` picks another.
+pub const DEFAULT_ENV_PREFIX: &str = "CARGO_DEV_INSTALL";
+
+pub fn validate_env_prefix(prefix: &str) -> Result<(), String> {
+    let mut chars = prefix.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "invalid env prefix '{prefix}': use letters, digits and underscores"
+        ))
+    }
+}
+
+/// Toolchain names are written into the script unquoted by the shell's
+/// rules, so only what rustup itself uses for names is accepted.
+pub fn validate_toolchain(toolchain: &str) -> Result<(), String> {
+    let valid = !toolchain.is_empty()
+        && !toolchain.starts_with('-')
+        && toolchain
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'));
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "invalid toolchain '{toolchain}': use letters, digits, '.', '-' and '_'"
+        ))
+    }
+}
+
+/// The variables exported to the program: repository root, the crate's
+/// manifest dir, the build profile, the wrapper name and the commit the
+/// build came from (empty when unknown).
+pub fn context_env(
+    prefix: &str,
+    repo_root: &Path,
+    manifest_dir: &Path,
+    wrapper: &str,
+    commit: &str,
+) -> Vec<(String, String)> {
+    [
+        ("REPO_ROOT", repo_root.display().to_string()),
+        ("MANIFEST_DIR", manifest_dir.display().to_string()),
+        ("PROFILE", "release".to_string()),
+        ("WRAPPER", wrapper.to_string()),
+        ("GIT_COMMIT", commit.to_string()),
+    ]
+    .into_iter()
+    .map(|(name, value)| (format!("{prefix}_{name}"), value))
+    .collect()
+}
+
+pub fn render_wrapper(crate_root: &Path, bin_name: &str, options: &WrapperOptions) -> String {
+    let mut vars = format!("REPO=\"{}\"\nBIN=\"{}\"\n", crate_root.display(), bin_name);
+    let mut build_env = String::new();
+    if options.sanitize_env {
+        vars.push_str(&format!(
+            "SANITIZED_VARS=\"{}\"\n",
+            SANITIZED_ENV_VARS.join(" ")
+        ));
+        build_env.push_str("unset $SANITIZED_VARS && ");
+    }
+    match &options.toolchain {
+        Some(toolchain) => {
+            vars.push_str(&format!("TOOLCHAIN=\"{toolchain}\"\n"));
+            build_env.push_str("RUSTUP_TOOLCHAIN=\"$TOOLCHAIN\" ");
+        }
+        // An inherited pin (say from `cargo +nightly`) would override the
+        // repository's rust-toolchain file; sanitizing unsets it already.
+        None if !options.sanitize_env => build_env.push_str("unset RUSTUP_TOOLCHAIN && "),
+        None => {}
+    }
+    if let Some(cargo_home) = &options.cargo_home {
+        build_env.push_str(&format!("CARGO_HOME=\"{}\" ", cargo_home.display()));
+    }
+    if let Some(rustup_home) = &options.rustup_home {
+        build_env.push_str(&format!("RUSTUP_HOME=\"{}\" ", rustup_home.display()));
+    }
+    if let Some(target_dir) = &options.target_dir {
+        vars.push_str(&format!("TARGET_DIR=\"{}\"\n", target_dir.display()));
+        build_env.push_str("CARGO_TARGET_DIR=\"$TARGET_DIR\" ");
+    }
+    match &options.cargo {
+        Some(cargo) => vars.push_str(&format!("CARGO_BIN=\"{}\"\n", cargo.display())),
+        None => vars.push_str("CARGO_BIN=\"\"\n"),
+    }
+    vars.push_str(&format!(
+        "BUILD_OUTPUT_MODE=\"{}\"\n",
+        options.build_output.name()
+    ));
+
+    // The build closes the lock descriptors: a process that cargo or a build
+    // script leaves running in the background would otherwise keep holding
+    // the lock and block every later invocation.
+    let (lock_acquire, record_exe, lock_release, build) = match &options.lock_path {
+        Some(lock_path) => {
+            vars.push_str(&format!("LOCK_FILE=\"{}\"\n", lock_path.display()));
+            (
+                LOCK_ACQUIRE,
+                LOCK_RECORD_EXE,
+                LOCK_RELEASE,
+                "build 9>&- 8>&-",
+            )
+        }
+        None => ("", "", "", "build"),
+    };
+
+    // `$0` is the path the wrapper (or an alias symlink to it) was run by.
+    let exec = if options.preserve_argv0 {
+        vars.push_str("ARGV0=\"${0##*/}\"\n");
+        "exec -a \"$ARGV0\""
+    } else {
+        "exec"
+    };
+
+    // `cargo run` would apply the repository's `[env]` and target runner,
+    // which only the launcher can read from cargo config.
+    let run_program = match &options.launcher {
+        Some(launcher) => {
+            vars.push_str(&format!("LAUNCHER=\"{}\"\n", launcher.display()));
+            let mut flags = String::new();
+            if let Some(cargo_home) = &options.cargo_home {
+                flags.push_str(&format!(" --cargo-home \"{}\"", cargo_home.display()));
+            }
+            if options.toolchain.is_some() {
+                flags.push_str(" --toolchain \"$TOOLCHAIN\"");
+            }
+            if options.preserve_argv0 {
+                flags.push_str(" --argv0 \"$ARGV0\"");
+            }
+            format!(
+                "\nrun_program() {{\n    if [ -x \"$LAUNCHER\" ]; then\n        exec \"$LAUNCHER\" exec-program --repo \"$REPO\"{flags} -- \"$@\"\n    fi\n    {exec} \"$@\"\n}}\n"
+            )
+        }
+        None => format!("\nrun_program() {{\n    {exec} \"$@\"\n}}\n"),
+    };
+
+    // The commit is read after the build; a last good build runs with an
+    // empty one, since it may come from any earlier commit.
+    let (context_fn, context, fallback_context) = match &options.env_prefix {
+        Some(prefix) => {
+            let repo_root = options.repo_root.as_deref().unwrap_or(crate_root);
+            vars.push_str(&format!("REPO_ROOT=\"{}\"\n", repo_root.display()));
+            (
+                format!(
+                    "\nexport_context() {{\n    export {prefix}_REPO_ROOT=\"$REPO_ROOT\" {prefix}_MANIFEST_DIR=\"$REPO\" {prefix}_PROFILE=release {prefix}_WRAPPER=\"$BIN\" {prefix}_GIT_COMMIT=\"$1\"\n}}\n"
+                ),
+                "export_context \"$(git -C \"$REPO\" rev-parse HEAD 2>/dev/null || true)\"\n",
+                "            export_context \"\"\n",
+            )
+        }
+        None => (String::new(), "", ""),
+    };
+
+    let (save_last_good, fallback) = match &options.last_good {
+        Some(last_good) => {
+            vars.push_str(&format!("LAST_GOOD=\"{}\"\n", last_good.display()));
+            let run_last_good = if options.strict {
+                "            (run_program \"$LAST_GOOD\" \"$@\") || true\n"
+            } else {
+                "            run_program \"$LAST_GOOD\" \"$@\"\n"
+            };
+            (
+                SAVE_LAST_GOOD.to_string(),
+                format!(
+                    "{RUN_LAST_GOOD}{fallback_context}{run_last_good}{LAST_GOOD_MISMATCH}        fi\n"
+                ),
+            )
+        }
+        None => (String::new(), String::new()),
+    };
+
+    let header = match options.style {
+        WrapperStyle::Sh => "#!/bin/sh\nset -eu\n",
+        WrapperStyle::Bash | WrapperStyle::Native => "#!/usr/bin/env bash\nset -euo pipefail\n",
+    };
+
+    // Cargo discovers `.cargo/config.toml` from its working directory, so the
+    // build runs from the repository while the program keeps the caller's cwd.
+    // The body sticks to POSIX sh so every script style shares it.
+    let script = format!(
+        r#"{header}
+{vars}
+if [ ! -x "$CARGO_BIN" ]; then
+    CARGO_BIN="$(command -v cargo || true)"
+fi
+if [ -z "$CARGO_BIN" ]; then
+    echo "$BIN: cargo not found; install it or add it to PATH" >&2
+    exit 127
+fi
+
+case "${{{BUILD_OUTPUT_ENV}:-}}" in
+    cargo | quiet) BUILD_OUTPUT_MODE="${BUILD_OUTPUT_ENV}" ;;
+esac
+
+build() {{
+    cd "$REPO" && {build_env}"$CARGO_BIN" build --quiet --release --manifest-path "$REPO/Cargo.toml" --bin "$BIN" --message-format=json-render-diagnostics
+}}
+{context_fn}{run_program}
+EXE=""
+{lock_acquire}if [ -z "$EXE" ]; then
+    BUILD_STATUS=0
+    if [ "$BUILD_OUTPUT_MODE" = quiet ]; then
+        BUILD_LOG="$(mktemp)"
+        if [ -t 2 ]; then
+            printf '%s: building…' "$BIN" >&2
+        fi
+        BUILD_OUTPUT="$({build} 2>"$BUILD_LOG")" || BUILD_STATUS=$?
+        if [ -t 2 ]; then
+            printf '\r\033[K' >&2
+        fi
+        if [ "$BUILD_STATUS" -ne 0 ]; then
+            cat "$BUILD_LOG" >&2
+        fi
+        rm -f "$BUILD_LOG"
+    else
+        BUILD_OUTPUT="$({build})" || BUILD_STATUS=$?
+    fi
+    if [ "$BUILD_STATUS" -ne 0 ]; then
+{fallback}        exit "$BUILD_STATUS"
+    fi
+    EXE="$(printf '%s\n' "$BUILD_OUTPUT" | sed -n 's/.*"executable":"\([^"]*\)".*/\1/p' | tail -n 1)"
+    if [ -z "$EXE" ]; then
+        echo "$BIN: cargo did not report an executable" >&2
+        exit 1
+    fi
+{save_last_good}{record_exe}fi
+{lock_release}
+{context}run_program "$EXE" "$@"
+"#
+    );
+    stamp(&script)
+}
+
+/// Version of the script `render_wrapper` produces. Bump it whenever the
+/// output for the same options changes, so `refresh` picks wrappers up.
+pub const TEMPLATE_VERSION: u32 = 1;
+
+const MARKER_PREFIX: &str = "# cargo-dev-install template=";
+
+/// The marker line every generated script carries after its shebang.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WrapperMarker {
+    pub template: u32,
+    /// Whether the script still matches the checksum it was written with.
+    pub intact: bool,
+}
+
+/// FNV-1a, which is enough to notice edits; this is not a security check.
+fn checksum(contents: &str) -> String {
+    let hash = contents
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+    format!("{hash:016x}")
+}
+
+/// Links `alias` next to the wrapper, pointing at the wrapper by its file
+/// name so the pair can be moved together. An existing link to the wrapper
+/// is kept; anything else at that path is left alone.
+pub fn write_alias(wrapper_path: &Path, alias: &str) -> Result<(), String> {
+    let alias_path = wrapper_path.with_file_name(alias);
+    let target = Path::new(wrapper_path.file_name().unwrap_or_default());
+    write_launcher_link(&alias_path, target, false).map_err(|err| {
+        if err.kind() == io::ErrorKind::AlreadyExists {
+            format!(
+                "failed to write alias: {} exists and is not a link to {}",
+                alias_path.display(),
+                target.display()
+            )
+        } else {
+            format!("failed to write alias {}: {err}", alias_path.display())
+        }
+    })
+}
+
+/// Removes the alias link next to `wrapper_path` if it still points at the
+/// wrapper.
+pub fn remove_alias(wrapper_path: &Path, alias: &str) -> io::Result<()> {
+    let alias_path = wrapper_path.with_file_name(alias);
+    match fs::read_link(&alias_path) {
+        Ok(target) if Some(target.as_os_str()) == wrapper_path.file_name() => {
+            fs::remove_file(&alias_path)
+        }
+        Ok(_) => Ok(()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        // Not a symlink, so not ours.
+        Err(err) if err.kind() == io::ErrorKind::InvalidInput => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+/// SHA-256 of a script wrapper's contents, as recorded in the registry.
+pub fn content_hash(contents: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+    let digest = Sha256::digest(contents);
+    let hex: String = digest.iter().map(|byte| format!("{byte:02x}")).collect();
+    format!("sha256:{hex}")
+}
+
+/// Name of a wrapper's entries in the cache directory: its file name and a
+/// hash of its full path, so same-named wrappers in different directories
+/// (such as `--local` installs in two repositories) never share a cache.
+pub fn cache_key(wrapper_path: &Path) -> String {
+    use sha2::{Digest, Sha256};
+    use std::os::unix::ffi::OsStrExt;
+    let digest = Sha256::digest(wrapper_path.as_os_str().as_bytes());
+    let hex: String = digest[..8]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    let name = wrapper_path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    format!("{name}-{hex}")
+}
+
+/// Hash of a native wrapper, which is a symlink to `target`.
+pub fn link_hash(target: &Path) -> String {
+    use std::os::unix::ffi::OsStrExt;
+    content_hash(&[b"symlink:", target.as_os_str().as_bytes()].concat())
+}
+
+/// Hashes whatever is installed at `wrapper_path` without following a
+/// symlink, comparable with the hash recorded at install time.
+pub fn wrapper_hash(wrapper_path: &Path) -> io::Result<String> {
+    if wrapper_path.symlink_metadata()?.file_type().is_symlink() {
+        return Ok(link_hash(&fs::read_link(wrapper_path)?));
+    }
+    Ok(content_hash(&fs::read(wrapper_path)?))
+}
+
+/// Inserts the marker with the template version and the checksum of the
+/// script without the marker line.
+fn stamp(script: &str) -> String {
+    let (shebang, rest) = script.split_once('\n').unwrap_or((script, ""));
     format!(
-        "#!/usr/bin/env bash\nset -euo pipefail\n\nREPO=\"{}\"\nexec cargo run --quiet --release --manifest-path \"$REPO/Cargo.toml\" -- \"$@\"\n",
-        crate_root.display()
+        "{shebang}\n{MARKER_PREFIX}{TEMPLATE_VERSION} checksum={}\n{rest}",
+        checksum(script)
     )
 }
 
-pub fn write_wrapper(wrapper_path: &Path, contents: &str, force: bool) -> io::Result<()> {
-    if wrapper_path.exists() && !force {
+/// Reads the marker of a generated script; `None` for files without one,
+/// such as wrappers written before templates were versioned.
+pub fn read_marker(contents: &str) -> Option<WrapperMarker> {
+    let (shebang, rest) = contents.split_once('\n')?;
+    let (marker, body) = rest.split_once('\n')?;
+    let (template, recorded) = marker
+        .strip_prefix(MARKER_PREFIX)?
+        .split_once(" checksum=")?;
+    Some(WrapperMarker {
+        template: template.parse().ok()?,
+        intact: recorded == checksum(&format!("{shebang}\n{body}")),
+    })
+}
+
+/// Whether `contents` is a wrapper written before scripts carried a marker,
+/// from the original `cargo run` template onwards: one of our fixed headers,
+/// the crate root in `REPO` and cargo pointed at `$REPO/Cargo.toml`.
+pub fn is_unversioned_wrapper(contents: &str) -> bool {
+    [
+        "#!/usr/bin/env bash\nset -euo pipefail\n\nREPO=\"",
+        "#!/bin/sh\nset -eu\n\nREPO=\"",
+    ]
+    .iter()
+    .find_map(|header| contents.strip_prefix(header))
+    .is_some_and(|body| body.contains("--manifest-path \"$REPO/Cargo.toml\""))
+}
+
+/// Whether the start of a file looks like a script generated by this tool.
+pub fn has_marker(head: &str) -> bool {
+    head.lines()
+        .nth(1)
+        .is_some_and(|line| line.starts_with(MARKER_PREFIX))
+}
+
+// Serializes the build phase across concurrent invocations with flock(1).
+// Only the first waiter announces the rebuild, and waiters reuse the
+// executable the lock holder just built instead of invoking cargo again.
+const LOCK_ACQUIRE: &str = r#"LOCKED=""
+if command -v flock >/dev/null 2>&1 && mkdir -p "${LOCK_FILE%/*}" 2>/dev/null; then
+    exec 9>"$LOCK_FILE"
+    LOCKED=1
+    if ! flock -n 9; then
+        exec 8>"$LOCK_FILE.notice"
+        if flock -n 8; then
+            echo "rebuilding $BIN…" >&2
+        fi
+        flock 9
+        exec 8>&-
+        EXE="$(cat "$LOCK_FILE.exe" 2>/dev/null || true)"
+        if [ ! -x "$EXE" ]; then
+            EXE=""
+        fi
+    fi
+    if [ -z "$EXE" ]; then
+        rm -f "$LOCK_FILE.exe"
+    fi
+fi
+"#;
+
+const LOCK_RECORD_EXE: &str = r#"    if [ -n "$LOCKED" ]; then
+        printf '%s\n' "$EXE" > "$LOCK_FILE.exe"
+    fi
+"#;
+
+// Keeps a copy of every freshly built binary, with the manifest, commit and
+// time it was built from, for use when the working tree stops compiling.
+const SAVE_LAST_GOOD: &str = r#"    if [ ! -e "$LAST_GOOD" ] || [ "$EXE" -nt "$LAST_GOOD" ] || [ "$(cat "$LAST_GOOD.source" 2>/dev/null || true)" != "$REPO/Cargo.toml" ]; then
+        if mkdir -p "${LAST_GOOD%/*}" && cp "$EXE" "$LAST_GOOD.tmp" && mv -f "$LAST_GOOD.tmp" "$LAST_GOOD"; then
+            printf '%s\n' "$REPO/Cargo.toml" > "$LAST_GOOD.source"
+            printf 'commit %s, built %s\n' "$(git -C "$REPO" rev-parse --short HEAD 2>/dev/null || echo unknown)" "$(date -u '+%Y-%m-%d %H:%M:%S UTC')" > "$LAST_GOOD.info"
+        else
+            echo "warning: $BIN: failed to save last good build" >&2
+        fi
+    fi
+"#;
+
+// Runs the last good build after a failed one, unless it was built from
+// another crate (the wrapper was reinstalled from a different manifest);
+// followed by either `exec` or, in strict mode, a plain run so the build's
+// exit status is still reported, and then `LAST_GOOD_MISMATCH`.
+const RUN_LAST_GOOD: &str = r#"        if [ -x "$LAST_GOOD" ] && [ "$(cat "$LAST_GOOD.source" 2>/dev/null || true)" = "$REPO/Cargo.toml" ]; then
+            echo "warning: $BIN: build failed at commit $(git -C "$REPO" rev-parse --short HEAD 2>/dev/null || echo unknown); running last good build ($(cat "$LAST_GOOD.info" 2>/dev/null || echo 'origin unknown'))" >&2
+            if [ -n "${LOCKED:-}" ]; then
+                exec 9>&-
+            fi
+"#;
+
+const LAST_GOOD_MISMATCH: &str = r#"        elif [ -x "$LAST_GOOD" ]; then
+            echo "warning: $BIN: not running the last good build, which was built from another crate" >&2
+"#;
+
+// The program must not inherit the lock, or it would hold it while running.
+const LOCK_RELEASE: &str = r#"if [ -n "$LOCKED" ]; then
+    exec 9>&-
+fi
+"#;
+
+/// What is at a wrapper path compared to what an install would write there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WrapperState {
+    Missing,
+    /// Byte-for-byte what would be written; installing is a no-op.
+    UpToDate,
+    /// A wrapper of ours with different contents.
+    Outdated,
+    /// Something this tool did not install.
+    Foreign,
+}
+
+/// Classifies `wrapper_path` against the script `contents` (or, for native
+/// wrappers, the `launcher` symlink target) an install would write. Files
+/// count as ours when `registered`, when they carry our template marker or
+/// when they are an unversioned wrapper from before markers existed.
+pub fn wrapper_state(
+    wrapper_path: &Path,
+    contents: &str,
+    launcher: Option<&Path>,
+    registered: bool,
+) -> WrapperState {
+    let Ok(metadata) = wrapper_path.symlink_metadata() else {
+        return WrapperState::Missing;
+    };
+
+    if metadata.file_type().is_symlink() {
+        let target = fs::read_link(wrapper_path).ok();
+        if launcher.is_some() && target.as_deref() == launcher {
+            return WrapperState::UpToDate;
+        }
+        let is_launcher = target
+            .as_deref()
+            .and_then(Path::file_name)
+            .is_some_and(|name| name == "cargo-dev-install");
+        return if registered || is_launcher {
+            WrapperState::Outdated
+        } else {
+            WrapperState::Foreign
+        };
+    }
+
+    let existing = fs::read_to_string(wrapper_path).ok();
+    if launcher.is_none() && existing.as_deref() == Some(contents) {
+        return WrapperState::UpToDate;
+    }
+    let ours = existing.as_deref().is_some_and(|existing| {
+        read_marker(existing).is_some() || is_unversioned_wrapper(existing)
+    });
+    if registered || ours {
+        WrapperState::Outdated
+    } else {
+        WrapperState::Foreign
+    }
+}
+
+/// Where `--force-foreign` keeps the file it replaced at `wrapper_path`: the
+/// wrapper path with `/` escaped as `%`, so every install location gets its
+/// own backup.
+pub fn backup_path(backups_dir: &Path, wrapper_path: &Path) -> PathBuf {
+    let escaped = wrapper_path
+        .to_string_lossy()
+        .trim_start_matches('/')
+        .replace('%', "%25")
+        .replace('/', "%");
+    backups_dir.join(escaped)
+}
+
+/// Moves a file or symlink, copying when `to` is on another filesystem.
+/// Refuses to replace an existing `to`.
+pub fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if to.symlink_metadata().is_ok() {
         return Err(io::Error::new(
             io::ErrorKind::AlreadyExists,
-            "wrapper already exists",
+            format!("{} already exists", to.display()),
         ));
     }
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    match fs::rename(from, to) {
+        Err(err) if err.kind() == io::ErrorKind::CrossesDevices => {
+            if from.symlink_metadata()?.file_type().is_symlink() {
+                std::os::unix::fs::symlink(fs::read_link(from)?, to)?;
+            } else {
+                fs::copy(from, to)?;
+            }
+            fs::remove_file(from)
+        }
+        result => result,
+    }
+}
 
+/// Moves the foreign file at `wrapper_path` to `backup_path` and runs
+/// `write` to put the wrapper in its place. If `write` fails the original is
+/// moved back, so a failed install never leaves the user's file behind in
+/// the backups directory with nothing recording it.
+pub fn replace_with_backup(
+    wrapper_path: &Path,
+    backup_path: &Path,
+    write: impl FnOnce() -> io::Result<()>,
+) -> Result<(), String> {
+    let path = wrapper_path.display();
+    move_file(wrapper_path, backup_path)
+        .map_err(|err| format!("failed to back up {path}: {err}"))?;
+    let Err(err) = write() else {
+        return Ok(());
+    };
+    match move_file(backup_path, wrapper_path) {
+        Ok(()) => Err(format!("failed to write wrapper: {err}")),
+        Err(restore) => Err(format!(
+            "failed to write wrapper: {err}; failed to restore the original from {}: {restore}",
+            backup_path.display()
+        )),
+    }
+}
+
+/// Writes a script wrapper. Without `force` an existing file is only
+/// accepted when it already has exactly these contents; the check and the
+/// write are a single atomic rename, so concurrent installers cannot both
+/// succeed with different contents.
+pub fn write_wrapper(wrapper_path: &Path, contents: &str, force: bool) -> io::Result<()> {
     if let Some(parent) = wrapper_path.parent() {
         fs::create_dir_all(parent)?;
     }
 
-    let temp_path = wrapper_path.with_extension("tmp");
-    fs::write(&temp_path, contents)?;
-
-    let mut perms = fs::metadata(&temp_path)?.permissions();
-    perms.set_mode(0o755);
-    fs::set_permissions(&temp_path, perms)?;
+    let temp = TempFile::create(wrapper_path, contents.as_bytes(), 0o755)?;
+    match temp.persist(wrapper_path, force) {
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+            if fs::read_to_string(wrapper_path).is_ok_and(|existing| existing == contents) {
+                return Ok(());
+            }
+            Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "wrapper already exists",
+            ))
+        }
+        result => result,
+    }
+}
 
-    fs::rename(&temp_path, wrapper_path)?;
+/// Installs a native wrapper: a symlink named after the wrapper that points
+/// at the `cargo-dev-install` launcher.
+pub fn write_launcher_link(wrapper_path: &Path, launcher: &Path, force: bool) -> io::Result<()> {
+    if let Some(parent) = wrapper_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
 
-    Ok(())
+    let temp = TempFile::symlink(wrapper_path, launcher)?;
+    match temp.persist(wrapper_path, force) {
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+            if fs::read_link(wrapper_path).is_ok_and(|target| target == launcher) {
+                return Ok(());
+            }
+            Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "wrapper already exists",
+            ))
+        }
+        result => result,
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::fs::OpenOptions;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn empty_env() -> crate::EnvSnapshot {
+        crate::EnvSnapshot {
+            home: None,
+            xdg_bin_home: None,
+            xdg_cache_home: None,
+            xdg_data_home: None,
+            path: None,
+            cargo: None,
+            cargo_home: None,
+            rustup_home: None,
+            current_exe: None,
+        }
+    }
 
     #[test]
     fn install_dir_prefers_xdg_bin_home() {
         let env = crate::EnvSnapshot {
             home: Some(PathBuf::from("/home/demo")),
             xdg_bin_home: Some(PathBuf::from("/custom/bin")),
-            path: None,
+            ..empty_env()
         };
         assert_eq!(install_dir(&env), Some(PathBuf::from("/custom/bin")));
     }
@@ -70,8 +922,7 @@ mod tests {
     fn install_dir_falls_back_to_home_local_bin() {
         let env = crate::EnvSnapshot {
             home: Some(PathBuf::from("/home/demo")),
-            xdg_bin_home: None,
-            path: None,
+            ..empty_env()
         };
         assert_eq!(
             install_dir(&env),
@@ -81,48 +932,466 @@ mod tests {
 
     #[test]
     fn install_dir_none_when_no_home() {
+        assert_eq!(install_dir(&empty_env()), None);
+    }
+
+    #[test]
+    fn cache_dir_prefers_xdg_cache_home() {
         let env = crate::EnvSnapshot {
-            home: None,
-            xdg_bin_home: None,
-            path: None,
+            home: Some(PathBuf::from("/home/demo")),
+            xdg_cache_home: Some(PathBuf::from("/custom/cache")),
+            ..empty_env()
+        };
+        assert_eq!(
+            cache_dir(&env),
+            Some(PathBuf::from("/custom/cache/cargo-dev-install"))
+        );
+    }
+
+    #[test]
+    fn cache_dir_falls_back_to_home_cache() {
+        let env = crate::EnvSnapshot {
+            home: Some(PathBuf::from("/home/demo")),
+            ..empty_env()
+        };
+        assert_eq!(
+            cache_dir(&env),
+            Some(PathBuf::from("/home/demo/.cache/cargo-dev-install"))
+        );
+    }
+
+    #[test]
+    fn data_dir_falls_back_to_home_local_share() {
+        let env = crate::EnvSnapshot {
+            home: Some(PathBuf::from("/home/demo")),
+            ..empty_env()
         };
-        assert_eq!(install_dir(&env), None);
+        assert_eq!(
+            data_dir(&env),
+            Some(PathBuf::from("/home/demo/.local/share/cargo-dev-install"))
+        );
     }
 
     #[test]
     fn is_on_path_detects_match() {
         let dir = Path::new("/home/demo/.local/bin");
         let path_var = "/usr/bin:/home/demo/.local/bin:/bin";
-        assert!(is_on_path(dir, Some(path_var)));
+        assert_eq!(
+            is_on_path(dir, Some(path_var), None, None),
+            PathStatus::Present
+        );
     }
 
     #[test]
     fn is_on_path_handles_missing_path() {
         let dir = Path::new("/home/demo/.local/bin");
-        assert!(!is_on_path(dir, None));
+        assert_eq!(is_on_path(dir, None, None, None), PathStatus::Missing);
     }
 
     #[test]
     fn is_on_path_returns_false_for_absent_dir() {
         let dir = Path::new("/opt/bin");
         let path_var = "/usr/bin:/home/demo/.local/bin:/bin";
-        assert!(!is_on_path(dir, Some(path_var)));
+        assert_eq!(
+            is_on_path(dir, Some(path_var), None, None),
+            PathStatus::Missing
+        );
+    }
+
+    #[test]
+    fn is_on_path_normalizes_entries() {
+        let dir = Path::new("/home/demo/.local/bin");
+        for path_var in [
+            "/usr/bin:/home/demo/.local/bin/",
+            "/home//demo/.local/bin",
+            "/home/demo/./.local/bin//",
+            "/home/demo/src/../.local/bin",
+        ] {
+            assert_eq!(
+                is_on_path(dir, Some(path_var), None, None),
+                PathStatus::Present,
+                "{path_var}"
+            );
+        }
+    }
+
+    #[test]
+    fn is_on_path_follows_symlinks_on_both_sides() {
+        let temp_dir = tempfile::tempdir().expect("temp dir");
+        let real = temp_dir.path().join("real");
+        let link = temp_dir.path().join("link");
+        fs::create_dir(&real).expect("create dir");
+        std::os::unix::fs::symlink(&real, &link).expect("symlink");
+
+        let path_var = link.display().to_string();
+        assert_eq!(
+            is_on_path(&real, Some(&path_var), None, None),
+            PathStatus::Present
+        );
+        let path_var = real.display().to_string();
+        assert_eq!(
+            is_on_path(&link, Some(&path_var), None, None),
+            PathStatus::Present
+        );
+    }
+
+    #[test]
+    fn is_on_path_reports_relative_and_tilde_entries() {
+        let dir = Path::new("/home/demo/.local/bin");
+        let home = Some(Path::new("/home/demo"));
+        let status =
+            |path_var: &str, cwd: &str| is_on_path(dir, Some(path_var), home, Some(Path::new(cwd)));
+
+        assert_eq!(
+            status("/usr/bin:~/.local/bin", "/"),
+            PathStatus::Tilde("~/.local/bin".to_string())
+        );
+        assert_eq!(
+            status("/usr/bin::/bin", "/home/demo/.local/bin"),
+            PathStatus::Relative(String::new())
+        );
+        assert_eq!(
+            status(".local/bin", "/home/demo"),
+            PathStatus::Relative(".local/bin".to_string())
+        );
+        assert_eq!(
+            status("~/.local/bin:/home/demo/.local/bin", "/"),
+            PathStatus::Present
+        );
+        assert_eq!(status(".local/bin", "/tmp"), PathStatus::Missing);
+        assert_eq!(status("~other/.local/bin", "/"), PathStatus::Missing);
     }
 
     #[test]
     fn render_wrapper_contains_expected_lines() {
-        let wrapper = render_wrapper(Path::new("/repo/root"));
+        let wrapper = render_wrapper(Path::new("/repo/root"), "demo", &WrapperOptions::default());
         assert!(wrapper.starts_with("#!/usr/bin/env bash\n"));
         assert!(wrapper.contains("set -euo pipefail\n"));
         assert!(wrapper.contains("REPO=\"/repo/root\"\n"));
+        assert!(wrapper.contains("BIN=\"demo\"\n"));
+        assert!(wrapper.contains("run_program() {\n    exec \"$@\"\n}\n"));
+        assert!(wrapper.contains("run_program \"$EXE\" \"$@\"\n"));
+    }
+
+    #[test]
+    fn render_wrapper_builds_from_repo_dir() {
+        let wrapper = render_wrapper(Path::new("/repo/root"), "demo", &WrapperOptions::default());
+        assert!(wrapper.contains(
+            "cd \"$REPO\" && unset RUSTUP_TOOLCHAIN && \"$CARGO_BIN\" build --quiet --release --manifest-path \"$REPO/Cargo.toml\" --bin \"$BIN\""
+        ));
+        assert!(!wrapper.contains("cargo run"));
+    }
+
+    #[test]
+    fn render_wrapper_pins_toolchain() {
+        let options = WrapperOptions {
+            toolchain: Some("nightly-2024-01-01".to_string()),
+            ..Default::default()
+        };
+        let wrapper = render_wrapper(Path::new("/repo/root"), "demo", &options);
+        assert!(wrapper.contains("TOOLCHAIN=\"nightly-2024-01-01\"\n"));
+        assert!(wrapper.contains("&& RUSTUP_TOOLCHAIN=\"$TOOLCHAIN\" \"$CARGO_BIN\" build"));
+        assert!(!wrapper.contains("unset RUSTUP_TOOLCHAIN"));
+
+        assert!(validate_toolchain("nightly-2024-01-01").is_ok());
+        assert!(validate_toolchain("1.80.0-x86_64-unknown-linux-gnu").is_ok());
+        assert!(validate_toolchain("").is_err());
+        assert!(validate_toolchain("nightly\"$(touch x)").is_err());
+        assert!(validate_toolchain("-x").is_err());
+    }
+
+    #[test]
+    fn render_wrapper_uses_absolute_cargo_with_fallback() {
+        let options = WrapperOptions {
+            cargo: Some(PathBuf::from("/home/demo/.cargo/bin/cargo")),
+            cargo_home: Some(PathBuf::from("/home/demo/.cargo")),
+            rustup_home: Some(PathBuf::from("/home/demo/.rustup")),
+            ..Default::default()
+        };
+        let wrapper = render_wrapper(Path::new("/repo/root"), "demo", &options);
+        assert!(wrapper.contains("CARGO_BIN=\"/home/demo/.cargo/bin/cargo\"\n"));
+        assert!(wrapper.contains("CARGO_BIN=\"$(command -v cargo || true)\""));
+        assert!(wrapper.contains(
+            "CARGO_HOME=\"/home/demo/.cargo\" RUSTUP_HOME=\"/home/demo/.rustup\" \"$CARGO_BIN\" build"
+        ));
+    }
+
+    #[test]
+    fn render_wrapper_sanitizes_env_for_build_only() {
+        let options = WrapperOptions {
+            sanitize_env: true,
+            ..Default::default()
+        };
+        let wrapper = render_wrapper(Path::new("/repo/root"), "cargo-demo", &options);
+        assert!(wrapper.contains("SANITIZED_VARS=\"CARGO CARGO_MANIFEST_DIR "));
+        assert!(wrapper.contains(" RUSTFLAGS "));
+        assert!(wrapper.contains(" RUSTC_WRAPPER "));
+        assert!(wrapper.contains("cd \"$REPO\" && unset $SANITIZED_VARS && \"$CARGO_BIN\" build"));
+    }
+
+    #[test]
+    fn render_wrapper_sets_isolated_target_dir() {
+        let options = WrapperOptions {
+            sanitize_env: true,
+            target_dir: Some(PathBuf::from(
+                "/home/demo/.cache/cargo-dev-install/targets/demo",
+            )),
+            ..Default::default()
+        };
+        let wrapper = render_wrapper(Path::new("/repo/root"), "demo", &options);
+        assert!(
+            wrapper.contains("TARGET_DIR=\"/home/demo/.cache/cargo-dev-install/targets/demo\"\n")
+        );
+        assert!(wrapper.contains(
+            "unset $SANITIZED_VARS && CARGO_TARGET_DIR=\"$TARGET_DIR\" \"$CARGO_BIN\" build"
+        ));
+    }
+
+    #[test]
+    fn cargo_path_prefers_rustup_proxy() {
+        let temp_dir = tempfile::tempdir().expect("temp dir");
+        let proxy = temp_dir.path().join(".cargo/bin/cargo");
+        fs::create_dir_all(proxy.parent().unwrap()).expect("create bin dir");
+        fs::write(&proxy, "").expect("write proxy");
+
+        let env = crate::EnvSnapshot {
+            home: Some(temp_dir.path().to_path_buf()),
+            cargo: Some(PathBuf::from("/toolchains/stable/bin/cargo")),
+            ..empty_env()
+        };
+        assert_eq!(cargo_path(&env), Some(proxy));
+    }
+
+    #[test]
+    fn cargo_path_falls_back_to_cargo_env() {
+        let temp_dir = tempfile::tempdir().expect("temp dir");
+        let env = crate::EnvSnapshot {
+            cargo_home: Some(temp_dir.path().to_path_buf()),
+            cargo: Some(PathBuf::from("/toolchains/stable/bin/cargo")),
+            ..empty_env()
+        };
+        assert_eq!(
+            cargo_path(&env),
+            Some(PathBuf::from("/toolchains/stable/bin/cargo"))
+        );
+    }
+
+    #[test]
+    fn render_wrapper_sh_style_is_posix() {
+        let options = WrapperOptions {
+            style: WrapperStyle::Sh,
+            ..Default::default()
+        };
+        let wrapper = render_wrapper(Path::new("/repo/root"), "demo", &options);
+        assert!(wrapper.starts_with("#!/bin/sh\n# cargo-dev-install template="));
+        assert!(wrapper.contains("\nset -eu\n"));
+        assert!(!wrapper.contains("bash"));
+        assert!(!wrapper.contains("pipefail"));
+        assert!(!wrapper.contains("[["));
+        assert!(wrapper.contains("    exec \"$@\"\n"));
+    }
+
+    #[test]
+    fn render_wrapper_carries_template_marker() {
+        let wrapper = render_wrapper(Path::new("/repo/root"), "demo", &WrapperOptions::default());
+        assert_eq!(
+            read_marker(&wrapper),
+            Some(WrapperMarker {
+                template: TEMPLATE_VERSION,
+                intact: true,
+            })
+        );
+
+        let edited = wrapper.replace("--release", "--release --offline");
+        assert_eq!(
+            read_marker(&edited),
+            Some(WrapperMarker {
+                template: TEMPLATE_VERSION,
+                intact: false,
+            })
+        );
+
+        assert_eq!(read_marker("#!/usr/bin/env bash\nexec demo \"$@\"\n"), None);
+    }
+
+    #[test]
+    fn render_wrapper_locks_build_phase_only() {
+        let options = WrapperOptions {
+            lock_path: Some(PathBuf::from("/cache/locks/demo.lock")),
+            ..Default::default()
+        };
+        let wrapper = render_wrapper(Path::new("/repo/root"), "demo", &options);
+        assert!(wrapper.contains("LOCK_FILE=\"/cache/locks/demo.lock\"\n"));
+        assert!(wrapper.contains("exec 9>\"$LOCK_FILE\"\n"));
+        assert!(wrapper.contains("echo \"rebuilding $BIN…\" >&2"));
+        assert!(wrapper.contains("BUILD_OUTPUT=\"$(build 9>&- 8>&-)\" || BUILD_STATUS=$?"));
+
+        let build = wrapper.find("\"$CARGO_BIN\" build").expect("build step");
+        let release = wrapper.find("exec 9>&-").expect("lock release");
+        let run = wrapper.find("run_program \"$EXE\"").expect("exec step");
+        assert!(build < release && release < run);
+    }
+
+    #[test]
+    fn render_wrapper_build_output_defaults_and_env_override() {
+        let wrapper = render_wrapper(Path::new("/repo/root"), "demo", &WrapperOptions::default());
+        assert!(wrapper.contains("BUILD_OUTPUT_MODE=\"cargo\"\n"));
+        assert!(wrapper.contains(
+            "    cargo | quiet) BUILD_OUTPUT_MODE=\"$CARGO_DEV_INSTALL_BUILD_OUTPUT\" ;;\n"
+        ));
+
+        let options = WrapperOptions {
+            build_output: BuildOutput::Quiet,
+            ..Default::default()
+        };
+        let wrapper = render_wrapper(Path::new("/repo/root"), "demo", &options);
+        assert!(wrapper.contains("BUILD_OUTPUT_MODE=\"quiet\"\n"));
+        assert!(wrapper.contains("BUILD_OUTPUT=\"$(build 2>\"$BUILD_LOG\")\" || BUILD_STATUS=$?"));
+        assert!(wrapper.contains("if [ -t 2 ]; then"));
+    }
+
+    #[test]
+    fn build_output_resolve_prefers_valid_env_value() {
+        assert_eq!(BuildOutput::Cargo.resolve(None), BuildOutput::Cargo);
+        assert_eq!(
+            BuildOutput::Cargo.resolve(Some("quiet")),
+            BuildOutput::Quiet
+        );
+        assert_eq!(
+            BuildOutput::Quiet.resolve(Some("cargo")),
+            BuildOutput::Cargo
+        );
+        assert_eq!(BuildOutput::Quiet.resolve(Some("loud")), BuildOutput::Quiet);
+    }
+
+    #[test]
+    fn render_wrapper_falls_back_to_last_good() {
+        let options = WrapperOptions {
+            last_good: Some(PathBuf::from("/cache/last-good/demo")),
+            ..Default::default()
+        };
+        let wrapper = render_wrapper(Path::new("/repo/root"), "demo", &options);
+        assert!(wrapper.contains("LAST_GOOD=\"/cache/last-good/demo\"\n"));
+        assert!(wrapper.contains("cp \"$EXE\" \"$LAST_GOOD.tmp\""));
+        assert!(wrapper.contains("printf '%s\\n' \"$REPO/Cargo.toml\" > \"$LAST_GOOD.source\""));
+        assert!(wrapper.contains("running last good build"));
+        assert!(wrapper.contains(
+            "if [ -x \"$LAST_GOOD\" ] && [ \"$(cat \"$LAST_GOOD.source\" 2>/dev/null || true)\" = \"$REPO/Cargo.toml\" ]; then"
+        ));
+        assert!(wrapper.contains("which was built from another crate"));
+        assert!(wrapper.contains("            run_program \"$LAST_GOOD\" \"$@\"\n"));
+        assert!(!wrapper.contains("|| true\n        fi\n        exit"));
+    }
+
+    #[test]
+    fn render_wrapper_exports_repository_context() {
+        let options = WrapperOptions {
+            env_prefix: Some("DEMO".to_string()),
+            repo_root: Some(PathBuf::from("/repo")),
+            last_good: Some(PathBuf::from("/cache/last-good/demo")),
+            ..WrapperOptions::default()
+        };
+        let wrapper = render_wrapper(Path::new("/repo/crates/demo"), "demo", &options);
+        assert!(wrapper.contains("REPO_ROOT=\"/repo\"\n"));
+        assert!(wrapper.contains(
+            "export DEMO_REPO_ROOT=\"$REPO_ROOT\" DEMO_MANIFEST_DIR=\"$REPO\" DEMO_PROFILE=release DEMO_WRAPPER=\"$BIN\" DEMO_GIT_COMMIT=\"$1\"\n"
+        ));
+        assert!(wrapper.contains(
+            "export_context \"$(git -C \"$REPO\" rev-parse HEAD 2>/dev/null || true)\"\nrun_program \"$EXE\" \"$@\"\n"
+        ));
+        assert!(wrapper.contains("export_context \"\"\n            run_program \"$LAST_GOOD\""));
+
+        let wrapper = render_wrapper(Path::new("/repo"), "demo", &WrapperOptions::default());
+        assert!(!wrapper.contains("export_context"));
+    }
+
+    #[test]
+    fn context_env_uses_prefix() {
+        let vars = context_env(
+            "DEMO",
+            Path::new("/repo"),
+            Path::new("/repo/demo"),
+            "demo",
+            "abc",
+        );
+        assert_eq!(
+            vars,
+            [
+                ("DEMO_REPO_ROOT".to_string(), "/repo".to_string()),
+                ("DEMO_MANIFEST_DIR".to_string(), "/repo/demo".to_string()),
+                ("DEMO_PROFILE".to_string(), "release".to_string()),
+                ("DEMO_WRAPPER".to_string(), "demo".to_string()),
+                ("DEMO_GIT_COMMIT".to_string(), "abc".to_string()),
+            ]
+        );
+        assert!(validate_env_prefix("MY_TOOL2").is_ok());
+        assert!(validate_env_prefix("").is_err());
+        assert!(validate_env_prefix("2X").is_err());
+        assert!(validate_env_prefix("MY-TOOL").is_err());
+    }
+
+    #[test]
+    fn render_wrapper_preserves_argv0() {
+        let options = WrapperOptions {
+            preserve_argv0: true,
+            last_good: Some(PathBuf::from("/cache/last-good/demo")),
+            ..WrapperOptions::default()
+        };
+        let wrapper = render_wrapper(Path::new("/repo"), "demo", &options);
+        assert!(wrapper.contains("ARGV0=\"${0##*/}\"\n"));
+        assert!(wrapper.contains("    exec -a \"$ARGV0\" \"$@\"\n"));
+        assert!(wrapper.contains("            run_program \"$LAST_GOOD\" \"$@\"\n"));
+
+        let wrapper = render_wrapper(Path::new("/repo"), "demo", &WrapperOptions::default());
+        assert!(!wrapper.contains("ARGV0"));
+    }
+
+    #[test]
+    fn render_wrapper_runs_program_through_launcher() {
+        let options = WrapperOptions {
+            cargo_home: Some(PathBuf::from("/home/u/.cargo")),
+            toolchain: Some("stable".to_string()),
+            preserve_argv0: true,
+            launcher: Some(PathBuf::from("/home/u/.cargo/bin/cargo-dev-install")),
+            ..Default::default()
+        };
+        let wrapper = render_wrapper(Path::new("/repo"), "demo", &options);
+        assert!(wrapper.contains("LAUNCHER=\"/home/u/.cargo/bin/cargo-dev-install\"\n"));
         assert!(wrapper.contains(
-            "exec cargo run --quiet --release --manifest-path \"$REPO/Cargo.toml\" -- \"$@\"\n"
+            "    if [ -x \"$LAUNCHER\" ]; then\n        exec \"$LAUNCHER\" exec-program --repo \"$REPO\" --cargo-home \"/home/u/.cargo\" --toolchain \"$TOOLCHAIN\" --argv0 \"$ARGV0\" -- \"$@\"\n    fi\n    exec -a \"$ARGV0\" \"$@\"\n"
         ));
     }
 
+    #[test]
+    fn render_wrapper_strict_last_good_keeps_failure_status() {
+        let options = WrapperOptions {
+            last_good: Some(PathBuf::from("/cache/last-good/demo")),
+            strict: true,
+            ..Default::default()
+        };
+        let wrapper = render_wrapper(Path::new("/repo/root"), "demo", &options);
+        assert!(
+            wrapper
+                .contains("            (run_program \"$LAST_GOOD\" \"$@\") || true\n        elif")
+        );
+        assert!(wrapper.contains("        fi\n        exit \"$BUILD_STATUS\"\n"));
+        assert!(!wrapper.contains("exec \"$LAST_GOOD\""));
+    }
+
+    #[test]
+    fn render_wrapper_without_lock_has_no_flock() {
+        let wrapper = render_wrapper(Path::new("/repo/root"), "demo", &WrapperOptions::default());
+        assert!(!wrapper.contains("flock"));
+        assert!(!wrapper.contains("LOCK_FILE"));
+    }
+
     #[test]
     fn render_wrapper_quotes_repo_paths_with_spaces() {
-        let wrapper = render_wrapper(Path::new("/path with spaces/repo"));
+        let wrapper = render_wrapper(
+            Path::new("/path with spaces/repo"),
+            "demo",
+            &WrapperOptions::default(),
+        );
         assert!(wrapper.contains("REPO=\"/path with spaces/repo\"\n"));
     }
 
@@ -149,6 +1418,184 @@ mod tests {
         assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
     }
 
+    #[test]
+    fn write_wrapper_accepts_identical_contents_without_force() {
+        let temp_dir = tempfile::tempdir().expect("temp dir");
+        let wrapper_path = temp_dir.path().join("demo");
+        write_wrapper(&wrapper_path, "echo demo\n", false).expect("write wrapper");
+        write_wrapper(&wrapper_path, "echo demo\n", false).expect("identical wrapper");
+    }
+
+    #[test]
+    fn wrapper_state_distinguishes_ours_from_foreign() {
+        let temp_dir = tempfile::tempdir().expect("temp dir");
+        let wrapper_path = temp_dir.path().join("demo");
+        let contents = render_wrapper(Path::new("/repo"), "demo", &WrapperOptions::default());
+        let state = |registered| wrapper_state(&wrapper_path, &contents, None, registered);
+
+        assert_eq!(state(false), WrapperState::Missing);
+
+        fs::write(&wrapper_path, &contents).expect("write");
+        assert_eq!(state(false), WrapperState::UpToDate);
+
+        let other = render_wrapper(Path::new("/moved"), "demo", &WrapperOptions::default());
+        fs::write(&wrapper_path, other).expect("write");
+        assert_eq!(state(false), WrapperState::Outdated);
+
+        // The template every release wrote before wrappers were versioned.
+        fs::write(
+            &wrapper_path,
+            "#!/usr/bin/env bash\nset -euo pipefail\n\nREPO=\"/repo\"\nexec cargo run --quiet --release --manifest-path \"$REPO/Cargo.toml\" -- \"$@\"\n",
+        )
+        .expect("write");
+        assert_eq!(state(false), WrapperState::Outdated);
+
+        fs::write(&wrapper_path, "#!/bin/sh\necho mine\n").expect("write");
+        assert_eq!(state(false), WrapperState::Foreign);
+        assert_eq!(state(true), WrapperState::Outdated);
+    }
+
+    #[test]
+    fn wrapper_state_checks_native_link_target() {
+        let temp_dir = tempfile::tempdir().expect("temp dir");
+        let wrapper_path = temp_dir.path().join("demo");
+        let launcher = Path::new("/opt/cargo-dev-install");
+        let state = || wrapper_state(&wrapper_path, "", Some(launcher), false);
+
+        std::os::unix::fs::symlink(launcher, &wrapper_path).expect("symlink");
+        assert_eq!(state(), WrapperState::UpToDate);
+
+        fs::remove_file(&wrapper_path).expect("remove");
+        std::os::unix::fs::symlink("/old/bin/cargo-dev-install", &wrapper_path).expect("symlink");
+        assert_eq!(state(), WrapperState::Outdated);
+
+        fs::remove_file(&wrapper_path).expect("remove");
+        std::os::unix::fs::symlink("/usr/bin/demo", &wrapper_path).expect("symlink");
+        assert_eq!(state(), WrapperState::Foreign);
+    }
+
+    #[test]
+    fn replace_with_backup_restores_original_when_write_fails() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let wrapper_path = dir.path().join("bin/demo");
+        let backup = dir.path().join("backups/demo");
+        fs::create_dir_all(wrapper_path.parent().unwrap()).expect("create bin");
+        fs::write(&wrapper_path, "theirs").expect("write original");
+
+        let err = replace_with_backup(&wrapper_path, &backup, || {
+            assert!(!wrapper_path.exists());
+            Err(io::Error::other("disk full"))
+        })
+        .expect_err("write fails");
+        assert!(err.contains("disk full"), "{err}");
+        assert_eq!(fs::read_to_string(&wrapper_path).expect("read"), "theirs");
+        assert!(!backup.exists());
+
+        replace_with_backup(&wrapper_path, &backup, || {
+            write_wrapper(&wrapper_path, "ours", false)
+        })
+        .expect("replace");
+        assert_eq!(fs::read_to_string(&wrapper_path).expect("read"), "ours");
+        assert_eq!(fs::read_to_string(&backup).expect("backup"), "theirs");
+    }
+
+    #[test]
+    fn backup_path_escapes_wrapper_path() {
+        assert_eq!(
+            backup_path(
+                Path::new("/data/backups"),
+                Path::new("/home/me/.local/bin/demo")
+            ),
+            PathBuf::from("/data/backups/home%me%.local%bin%demo")
+        );
+        assert_ne!(
+            backup_path(Path::new("/b"), Path::new("/x/a%b")),
+            backup_path(Path::new("/b"), Path::new("/x/a/b"))
+        );
+    }
+
+    #[test]
+    fn move_file_refuses_to_replace_destination() {
+        let temp_dir = tempfile::tempdir().expect("temp dir");
+        let from = temp_dir.path().join("from");
+        let to = temp_dir.path().join("backups/to");
+        fs::write(&from, "original").expect("write");
+
+        move_file(&from, &to).expect("move");
+        assert!(!from.exists());
+        assert_eq!(fs::read_to_string(&to).expect("read"), "original");
+
+        fs::write(&from, "second").expect("write");
+        let err = move_file(&from, &to).expect_err("destination exists");
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read_to_string(&to).expect("read"), "original");
+    }
+
+    #[test]
+    fn concurrent_installers_cannot_both_write_different_wrappers() {
+        let temp_dir = tempfile::tempdir().expect("temp dir");
+        let wrapper_path = temp_dir.path().join("demo");
+        let barrier = std::sync::Barrier::new(8);
+
+        let results: Vec<io::Result<()>> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..8)
+                .map(|i| {
+                    let (wrapper_path, barrier) = (&wrapper_path, &barrier);
+                    scope.spawn(move || {
+                        barrier.wait();
+                        write_wrapper(wrapper_path, &format!("echo {i}\n"), false)
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("join"))
+                .collect()
+        });
+
+        let winners: Vec<usize> = (0..8).filter(|&i| results[i].is_ok()).collect();
+        assert_eq!(winners.len(), 1, "{results:?}");
+        assert_eq!(
+            fs::read_to_string(&wrapper_path).expect("read"),
+            format!("echo {}\n", winners[0])
+        );
+        assert_eq!(fs::read_dir(temp_dir.path()).expect("read dir").count(), 1);
+    }
+
+    #[test]
+    fn wrappers_whose_names_share_a_stem_do_not_collide() {
+        let temp_dir = tempfile::tempdir().expect("temp dir");
+        write_wrapper(&temp_dir.path().join("foo"), "echo foo\n", false).expect("foo");
+        write_wrapper(&temp_dir.path().join("foo.cli"), "echo cli\n", false).expect("foo.cli");
+        write_wrapper(&temp_dir.path().join("foo.tmp"), "echo tmp\n", false).expect("foo.tmp");
+        assert_eq!(
+            fs::read_to_string(temp_dir.path().join("foo")).expect("read"),
+            "echo foo\n"
+        );
+    }
+
+    #[test]
+    fn aliases_link_to_the_wrapper_and_leave_other_files_alone() {
+        let temp_dir = tempfile::tempdir().expect("temp dir");
+        let wrapper_path = temp_dir.path().join("demo");
+        write_wrapper(&wrapper_path, "echo demo\n", false).expect("wrapper");
+
+        write_alias(&wrapper_path, "ls").expect("alias");
+        write_alias(&wrapper_path, "ls").expect("existing alias");
+        assert_eq!(
+            fs::read_link(temp_dir.path().join("ls")).expect("link"),
+            PathBuf::from("demo")
+        );
+
+        fs::write(temp_dir.path().join("cat"), "mine").expect("write");
+        assert!(write_alias(&wrapper_path, "cat").is_err());
+        remove_alias(&wrapper_path, "cat").expect("remove foreign");
+        assert!(temp_dir.path().join("cat").exists());
+
+        remove_alias(&wrapper_path, "ls").expect("remove alias");
+        assert!(temp_dir.path().join("ls").symlink_metadata().is_err());
+    }
+
     #[test]
     fn write_wrapper_overwrites_with_force() {
         let temp_dir = tempfile::tempdir().expect("temp dir");
@@ -166,6 +1613,7 @@ mod tests {
         let wrapper_path = temp_dir.path().join("demo");
         OpenOptions::new()
             .create(true)
+            .truncate(true)
             .write(true)
             .open(&wrapper_path)
             .expect("create file");
@@ -174,4 +1622,25 @@ mod tests {
         let contents = fs::read_to_string(&wrapper_path).expect("read wrapper");
         assert_eq!(contents, "echo demo\n");
     }
+
+    #[test]
+    fn write_launcher_link_creates_symlink() {
+        let temp_dir = tempfile::tempdir().expect("temp dir");
+        let wrapper_path = temp_dir.path().join("bin").join("demo");
+        let launcher = Path::new("/opt/cargo-dev-install");
+
+        write_launcher_link(&wrapper_path, launcher, false).expect("write link");
+        assert_eq!(fs::read_link(&wrapper_path).expect("read link"), launcher);
+        write_launcher_link(&wrapper_path, launcher, false).expect("identical link");
+
+        let err = write_launcher_link(&wrapper_path, Path::new("/other"), false)
+            .expect_err("expected already exists");
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+
+        write_launcher_link(&wrapper_path, Path::new("/other"), true).expect("overwrite");
+        assert_eq!(
+            fs::read_link(&wrapper_path).expect("read link"),
+            Path::new("/other")
+        );
+    }
 }
@@ -0,0 +1,735 @@
+use crate::cargo_config;
+use crate::git;
+use crate::install::{self, BuildOutput, WrapperStyle};
+use crate::registry::{self, Registry, RegistryEntry};
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File, TryLockError};
+use std::io::{IsTerminal, Write};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const PROGRAM_NAME: &str = "cargo-dev-install";
+
+/// Native wrappers are symlinks to this binary, so we were started through
+/// one when `argv[0]` is the name or an alias of a registered native
+/// wrapper. Any other name, such as a renamed copy of this binary, runs the
+/// CLI.
+pub fn is_launcher_invocation(argv0: &OsStr, registry: &Registry) -> bool {
+    let Some(name) = Path::new(argv0).file_name() else {
+        return false;
+    };
+    name != PROGRAM_NAME
+        && registry.wrappers.iter().any(|entry| {
+            entry.options.style == WrapperStyle::Native
+                && (*name == *entry.name || entry.aliases.iter().any(|alias| *name == **alias))
+        })
+}
+
+/// The registry, if `argv[0]` shows we were started through a native
+/// wrapper (see [`is_launcher_invocation`]).
+pub fn launcher_registry(
+    argv0: &OsStr,
+    env: &crate::EnvSnapshot,
+) -> Result<Option<Registry>, String> {
+    if Path::new(argv0)
+        .file_name()
+        .is_none_or(|name| name == PROGRAM_NAME)
+    {
+        return Ok(None);
+    }
+    let Some(registry_path) = registry::registry_path(env) else {
+        return Ok(None);
+    };
+    let registry = Registry::load(&registry_path)?;
+    Ok(is_launcher_invocation(argv0, &registry).then_some(registry))
+}
+
+/// A failed build and, when cargo ran, its exit status, which the launcher
+/// exits with like the script wrappers do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildError {
+    pub message: String,
+    pub status: Option<i32>,
+}
+
+impl From<String> for BuildError {
+    fn from(message: String) -> Self {
+        Self {
+            message,
+            status: None,
+        }
+    }
+}
+
+impl From<BuildError> for String {
+    fn from(err: BuildError) -> Self {
+        err.message
+    }
+}
+
+impl std::fmt::Display for BuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+pub fn run(
+    argv0: &OsStr,
+    args: &[OsString],
+    env: &crate::EnvSnapshot,
+    registry: &Registry,
+) -> Result<(), String> {
+    let argv0 = Path::new(argv0);
+    let name = argv0
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let cwd = std::env::current_dir().map_err(|err| format!("failed to read cwd: {err}"))?;
+
+    let entry = find_entry(registry, argv0, &cwd, env.path.as_deref())
+        .ok_or_else(|| format!("{name}: no native wrapper registered under this name"))?;
+
+    let (exe, built) = match locked_build(entry) {
+        Ok(exe) => (exe, true),
+        Err(err) => {
+            eprintln!("{err}");
+            let status = err.status.unwrap_or(1);
+            let Some(last_good) = entry.options.last_good.as_ref().filter(|exe| exe.is_file())
+            else {
+                std::process::exit(status);
+            };
+            if !last_good_matches(entry, last_good) {
+                eprintln!(
+                    "warning: {name}: not running the last good build, which was built from another crate"
+                );
+                std::process::exit(status);
+            }
+            let info = fs::read_to_string(with_suffix(last_good, "info"))
+                .map(|info| info.trim_end().to_string())
+                .unwrap_or_else(|_| "origin unknown".to_string());
+            eprintln!(
+                "warning: {name}: build failed at commit {}; running last good build ({info})",
+                short_head(&entry.crate_root)
+            );
+            if entry.options.strict {
+                let _ = program(last_good, &name, entry, false)?.args(args).status();
+                std::process::exit(status);
+            }
+            (last_good.clone(), false)
+        }
+    };
+    let err = program(&exe, &name, entry, built)?.args(args).exec();
+    Err(format!(
+        "{name}: failed to execute {}: {err}",
+        exe.display()
+    ))
+}
+
+/// Short hash of the commit checked out in `crate_root`, like
+/// `git rev-parse --short HEAD || echo unknown` in the script wrappers.
+fn short_head(crate_root: &Path) -> String {
+    git::run(crate_root, &["rev-parse", "--short", "HEAD"])
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+/// The command running the built program, with `argv[0]` set to the
+/// invoked name when the wrapper preserves it and the repository context
+/// exported. `built` is false for a last good build, whose commit is unknown.
+fn program(exe: &Path, name: &str, entry: &RegistryEntry, built: bool) -> Result<Command, String> {
+    let mut cmd = cargo_config::command(
+        exe,
+        entry.options.preserve_argv0.then_some(OsStr::new(name)),
+        &entry.crate_root,
+        entry.options.cargo_home.as_deref(),
+        entry.options.toolchain.as_deref(),
+    )
+    .map_err(|err| format!("{name}: {err}"))?;
+    if let Some(prefix) = &entry.options.env_prefix {
+        let commit = built
+            .then(|| git::run(&entry.crate_root, &["rev-parse", "HEAD"]).ok())
+            .flatten()
+            .unwrap_or_default();
+        let repo_root = entry.options.repo_root.as_deref();
+        cmd.envs(install::context_env(
+            prefix,
+            repo_root.unwrap_or(&entry.crate_root),
+            &entry.crate_root,
+            &entry.name,
+            &commit,
+        ));
+    }
+    Ok(cmd)
+}
+
+/// Runs a program a script wrapper built the way [`run`] does, applying the
+/// repository's `[env]` and runner. Scripts cannot read cargo config.
+pub fn exec_program(args: &crate::cli::ExecProgramArgs) -> Result<(), String> {
+    let Some((exe, program_args)) = args.command.split_first() else {
+        return Err("exec-program needs the executable to run".to_string());
+    };
+    let exe = Path::new(exe);
+    let err = cargo_config::command(
+        exe,
+        args.argv0.as_deref(),
+        &args.repo,
+        args.cargo_home.as_deref(),
+        args.toolchain.as_deref(),
+    )?
+    .args(program_args)
+    .exec();
+    Err(format!("failed to execute {}: {err}", exe.display()))
+}
+
+/// Resolves the registry entry for the invoked command. A bare name is
+/// looked up on `PATH` like the shell did; if that is ambiguous we fall back
+/// to the only native wrapper with that name.
+pub fn find_entry<'a>(
+    registry: &'a Registry,
+    argv0: &Path,
+    cwd: &Path,
+    path_var: Option<&str>,
+) -> Option<&'a RegistryEntry> {
+    let name = argv0.file_name()?;
+    let candidates: Vec<&RegistryEntry> = registry
+        .wrappers
+        .iter()
+        .filter(|entry| {
+            entry.options.style == WrapperStyle::Native
+                && (*name == *entry.name || entry.aliases.iter().any(|alias| *name == **alias))
+        })
+        .collect();
+
+    let invoked = if argv0.components().count() > 1 {
+        Some(cwd.join(argv0))
+    } else {
+        path_var.and_then(|path_var| {
+            std::env::split_paths(path_var)
+                .map(|dir| dir.join(name))
+                .find(|candidate| candidate.symlink_metadata().is_ok())
+        })
+    };
+
+    if let Some(invoked) = invoked
+        && let Some(entry) = candidates
+            .iter()
+            .find(|entry| entry.wrapper_path.with_file_name(name) == invoked)
+    {
+        return Some(entry);
+    }
+
+    match candidates.as_slice() {
+        [entry] => Some(entry),
+        _ => None,
+    }
+}
+
+/// The cargo invocation that builds the wrapper's binary, equivalent to the
+/// build step of the script wrappers.
+pub fn build_command(entry: &RegistryEntry) -> Command {
+    let options = &entry.options;
+    let cargo = options
+        .cargo
+        .clone()
+        .filter(|cargo| cargo.is_file())
+        .unwrap_or_else(|| PathBuf::from("cargo"));
+
+    let mut cmd = Command::new(cargo);
+    cmd.current_dir(&entry.crate_root);
+    if options.sanitize_env {
+        for var in install::SANITIZED_ENV_VARS {
+            cmd.env_remove(var);
+        }
+    }
+    if let Some(cargo_home) = &options.cargo_home {
+        cmd.env("CARGO_HOME", cargo_home);
+    }
+    if let Some(rustup_home) = &options.rustup_home {
+        cmd.env("RUSTUP_HOME", rustup_home);
+    }
+    // Without a pin, rustup reads the repository's rust-toolchain file,
+    // which an inherited RUSTUP_TOOLCHAIN would override.
+    match &options.toolchain {
+        Some(toolchain) => cmd.env("RUSTUP_TOOLCHAIN", toolchain),
+        None => cmd.env_remove("RUSTUP_TOOLCHAIN"),
+    };
+    if let Some(target_dir) = &options.target_dir {
+        cmd.env("CARGO_TARGET_DIR", target_dir);
+    }
+
+    cmd.args(["build", "--quiet", "--release", "--manifest-path"])
+        .arg(&entry.manifest_path)
+        .args(["--bin", &entry.bin_name])
+        .arg("--message-format=json-render-diagnostics");
+    cmd
+}
+
+/// Builds under the wrapper's advisory lock, mirroring the script wrappers:
+/// only the first waiter announces the rebuild, and waiters reuse the
+/// executable the lock holder produced. The lock is released on return, so
+/// the program never inherits it.
+pub fn locked_build(entry: &RegistryEntry) -> Result<PathBuf, BuildError> {
+    let Some(lock_path) = &entry.options.lock_path else {
+        return build_saving_last_good(entry);
+    };
+    let lock = match open_lock(lock_path) {
+        Ok(lock) => lock,
+        Err(_) => return build_saving_last_good(entry),
+    };
+    let exe_record = with_suffix(lock_path, "exe");
+
+    match lock.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) => {
+            let notice = open_lock(&with_suffix(lock_path, "notice"))
+                .map_err(|err| format!("{}: failed to open lock: {err}", entry.name))?;
+            if notice.try_lock().is_ok() {
+                eprintln!("rebuilding {}…", entry.name);
+            }
+            lock.lock()
+                .map_err(|err| format!("{}: failed to lock: {err}", entry.name))?;
+            drop(notice);
+
+            if let Ok(recorded) = fs::read_to_string(&exe_record) {
+                let exe = PathBuf::from(recorded.trim_end_matches('\n'));
+                if exe.is_file() {
+                    return Ok(exe);
+                }
+            }
+        }
+        Err(TryLockError::Error(err)) => {
+            return Err(format!("{}: failed to lock: {err}", entry.name).into());
+        }
+    }
+
+    let _ = fs::remove_file(&exe_record);
+    let exe = build_saving_last_good(entry)?;
+    let _ = fs::write(&exe_record, format!("{}\n", exe.display()));
+    Ok(exe)
+}
+
+/// [`build`], keeping a copy of the result for `--fallback-last-good`.
+fn build_saving_last_good(entry: &RegistryEntry) -> Result<PathBuf, BuildError> {
+    let exe = build(entry)?;
+    save_last_good(entry, &exe);
+    Ok(exe)
+}
+
+/// Whether the saved last good build came from the entry's manifest rather
+/// than from a crate the wrapper was previously installed from.
+fn last_good_matches(entry: &RegistryEntry, last_good: &Path) -> bool {
+    fs::read_to_string(with_suffix(last_good, "source"))
+        .is_ok_and(|source| Path::new(source.trim_end()) == entry.crate_root.join("Cargo.toml"))
+}
+
+/// Keeps a copy of a freshly built binary, with the manifest, commit and time
+/// it was built from, in the same layout the script wrappers use.
+fn save_last_good(entry: &RegistryEntry, exe: &Path) {
+    let Some(last_good) = &entry.options.last_good else {
+        return;
+    };
+    let modified = |path: &Path| fs::metadata(path).and_then(|meta| meta.modified());
+    if let (Ok(built), Ok(saved)) = (modified(exe), modified(last_good))
+        && built <= saved
+        && last_good_matches(entry, last_good)
+    {
+        return;
+    }
+
+    let temp_path = with_suffix(last_good, "tmp");
+    let saved = last_good
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|()| fs::copy(exe, &temp_path))
+        .and_then(|_| fs::rename(&temp_path, last_good));
+    if saved.is_err() {
+        eprintln!("warning: {}: failed to save last good build", entry.name);
+        return;
+    }
+    let source = format!("{}\n", entry.crate_root.join("Cargo.toml").display());
+    let _ = fs::write(with_suffix(last_good, "source"), source);
+
+    let commit = short_head(&entry.crate_root);
+    let info = format!("commit {commit}, built {}\n", format_utc(SystemTime::now()));
+    let _ = fs::write(with_suffix(last_good, "info"), info);
+}
+
+/// Formats a timestamp like `date -u '+%Y-%m-%d %H:%M:%S UTC'`.
+fn format_utc(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    let (days, secs_of_day) = (secs / 86_400, secs % 86_400);
+
+    // Civil-from-days conversion for the proleptic Gregorian calendar.
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02} UTC",
+        secs_of_day / 3_600,
+        secs_of_day % 3_600 / 60,
+        secs_of_day % 60
+    )
+}
+
+fn open_lock(path: &Path) -> std::io::Result<File> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+}
+
+/// `demo.lock` -> `demo.lock.<suffix>`, matching `$LOCK_FILE.<suffix>` in scripts.
+pub(crate) fn with_suffix(lock_path: &Path, suffix: &str) -> PathBuf {
+    let mut path = lock_path.as_os_str().to_owned();
+    path.push(".");
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// Runs [`build_command`] and returns the built executable.
+pub fn build(entry: &RegistryEntry) -> Result<PathBuf, BuildError> {
+    let mode = entry
+        .options
+        .build_output
+        .resolve(std::env::var(install::BUILD_OUTPUT_ENV).ok().as_deref());
+
+    let mut cmd = build_command(entry);
+    cmd.stdin(Stdio::null());
+    let output = match mode {
+        BuildOutput::Cargo => cmd.stderr(Stdio::inherit()).output(),
+        BuildOutput::Quiet => {
+            let progress = std::io::stderr().is_terminal();
+            if progress {
+                eprint!("{}: building…", entry.name);
+            }
+            let output = cmd.stderr(Stdio::piped()).output();
+            if progress {
+                eprint!("\r\x1b[K");
+            }
+            output
+        }
+    }
+    .map_err(|err| format!("{}: failed to run cargo: {err}", entry.name))?;
+
+    if !output.status.success() {
+        if mode == BuildOutput::Quiet {
+            let _ = std::io::stderr().write_all(&output.stderr);
+        }
+        return Err(BuildError {
+            message: format!("{}: build failed", entry.name),
+            status: output.status.code(),
+        });
+    }
+
+    parse_executable(&String::from_utf8_lossy(&output.stdout))
+        .ok_or_else(|| format!("{}: cargo did not report an executable", entry.name).into())
+}
+
+/// Extracts the last executable reported by cargo's JSON build messages.
+pub fn parse_executable(build_output: &str) -> Option<PathBuf> {
+    build_output
+        .lines()
+        .rev()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .filter(|message| message["reason"] == "compiler-artifact")
+        .find_map(|message| message["executable"].as_str().map(PathBuf::from))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::install::WrapperOptions;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn native_entry(name: &str, wrapper_path: &Path) -> RegistryEntry {
+        RegistryEntry {
+            name: name.to_string(),
+            wrapper_path: wrapper_path.to_path_buf(),
+            crate_root: PathBuf::from("/repo"),
+            manifest_path: PathBuf::from("/repo/Cargo.toml"),
+            bin_name: name.to_string(),
+            package: None,
+            options: WrapperOptions {
+                style: WrapperStyle::Native,
+                ..Default::default()
+            },
+            snapshot: None,
+            checkout: None,
+            backup: None,
+            hash: None,
+            aliases: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn launcher_invocation_depends_on_registered_names() {
+        let mut registry = Registry::default();
+        let mut demo = native_entry("demo", Path::new("/home/demo/.local/bin/demo"));
+        demo.aliases = vec!["dm".to_string()];
+        registry.upsert(demo);
+
+        assert!(!is_launcher_invocation(
+            OsStr::new("cargo-dev-install"),
+            &registry
+        ));
+        assert!(!is_launcher_invocation(
+            OsStr::new("/home/demo/.cargo/bin/cargo-dev-install"),
+            &registry
+        ));
+        assert!(is_launcher_invocation(
+            OsStr::new("/home/demo/.local/bin/demo"),
+            &registry
+        ));
+        assert!(is_launcher_invocation(OsStr::new("demo"), &registry));
+        assert!(is_launcher_invocation(OsStr::new("dm"), &registry));
+        // A renamed copy of this binary is still the CLI.
+        assert!(!is_launcher_invocation(
+            OsStr::new("cargo-dev-install-1.2"),
+            &registry
+        ));
+    }
+
+    #[test]
+    fn find_entry_matches_invoked_path() {
+        let mut registry = Registry::default();
+        registry.upsert(native_entry("demo", Path::new("/a/bin/demo")));
+        registry.upsert(native_entry("demo", Path::new("/b/bin/demo")));
+
+        let entry =
+            find_entry(&registry, Path::new("/b/bin/demo"), Path::new("/"), None).expect("entry");
+        assert_eq!(entry.wrapper_path, PathBuf::from("/b/bin/demo"));
+    }
+
+    #[test]
+    fn find_entry_searches_path_for_bare_name() {
+        let temp_dir = tempfile::tempdir().expect("temp dir");
+        let first = temp_dir.path().join("first");
+        let second = temp_dir.path().join("second");
+        fs::create_dir_all(&first).expect("create first");
+        fs::create_dir_all(&second).expect("create second");
+        fs::write(second.join("demo"), "").expect("write wrapper");
+
+        let mut registry = Registry::default();
+        registry.upsert(native_entry("demo", &first.join("demo")));
+        registry.upsert(native_entry("demo", &second.join("demo")));
+
+        let path_var = format!("{}:{}", first.display(), second.display());
+        let entry = find_entry(
+            &registry,
+            Path::new("demo"),
+            Path::new("/"),
+            Some(&path_var),
+        )
+        .expect("entry");
+        assert_eq!(entry.wrapper_path, second.join("demo"));
+    }
+
+    #[test]
+    fn find_entry_falls_back_to_single_candidate() {
+        let mut registry = Registry::default();
+        registry.upsert(native_entry("demo", Path::new("/a/bin/demo")));
+
+        let entry = find_entry(&registry, Path::new("demo"), Path::new("/"), None);
+        assert!(entry.is_some());
+        assert!(find_entry(&registry, Path::new("other"), Path::new("/"), None).is_none());
+    }
+
+    #[test]
+    fn find_entry_resolves_aliases() {
+        let mut registry = Registry::default();
+        let mut entry = native_entry("box", Path::new("/a/bin/box"));
+        entry.aliases = vec!["ls".to_string()];
+        registry.upsert(entry);
+        registry.upsert(native_entry("box", Path::new("/b/bin/box")));
+
+        let entry =
+            find_entry(&registry, Path::new("/a/bin/ls"), Path::new("/"), None).expect("entry");
+        assert_eq!(entry.wrapper_path, PathBuf::from("/a/bin/box"));
+        assert!(find_entry(&registry, Path::new("/b/bin/ls"), Path::new("/"), None).is_some());
+        assert!(find_entry(&registry, Path::new("cat"), Path::new("/"), None).is_none());
+    }
+
+    #[test]
+    fn find_entry_ignores_script_wrappers() {
+        let mut registry = Registry::default();
+        let mut entry = native_entry("demo", Path::new("/a/bin/demo"));
+        entry.options.style = WrapperStyle::Bash;
+        registry.upsert(entry);
+
+        assert!(find_entry(&registry, Path::new("/a/bin/demo"), Path::new("/"), None).is_none());
+    }
+
+    #[test]
+    fn build_command_mirrors_wrapper_options() {
+        let mut entry = native_entry("demo", Path::new("/a/bin/demo"));
+        entry.options.toolchain = Some("nightly".to_string());
+        entry.options.target_dir = Some(PathBuf::from("/cache/demo"));
+        entry.options.sanitize_env = true;
+
+        let cmd = build_command(&entry);
+        assert_eq!(cmd.get_current_dir(), Some(Path::new("/repo")));
+        let args: Vec<_> = cmd.get_args().collect();
+        assert_eq!(
+            args,
+            [
+                "build",
+                "--quiet",
+                "--release",
+                "--manifest-path",
+                "/repo/Cargo.toml",
+                "--bin",
+                "demo",
+                "--message-format=json-render-diagnostics",
+            ]
+        );
+
+        let envs: Vec<_> = cmd.get_envs().collect();
+        assert!(envs.contains(&(OsStr::new("RUSTUP_TOOLCHAIN"), Some(OsStr::new("nightly")))));
+        assert!(envs.contains(&(
+            OsStr::new("CARGO_TARGET_DIR"),
+            Some(OsStr::new("/cache/demo"))
+        )));
+        assert!(envs.contains(&(OsStr::new("RUSTFLAGS"), None)));
+    }
+
+    #[test]
+    fn with_suffix_appends_suffix() {
+        assert_eq!(
+            with_suffix(Path::new("/cache/locks/foo.cli.lock"), "exe"),
+            PathBuf::from("/cache/locks/foo.cli.lock.exe")
+        );
+    }
+
+    #[test]
+    fn locked_build_reuses_executable_after_waiting() {
+        let temp_dir = tempfile::tempdir().expect("temp dir");
+        let lock_path = temp_dir.path().join("locks/demo.lock");
+        let exe = temp_dir.path().join("demo-bin");
+        fs::write(&exe, "").expect("write exe");
+
+        let mut entry = native_entry("demo", Path::new("/a/bin/demo"));
+        entry.options.lock_path = Some(lock_path.clone());
+
+        let holder = open_lock(&lock_path).expect("open lock");
+        holder.lock().expect("hold lock");
+        fs::write(
+            with_suffix(&lock_path, "exe"),
+            format!("{}\n", exe.display()),
+        )
+        .expect("record exe");
+
+        let waiter = std::thread::spawn(move || locked_build(&entry));
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        drop(holder);
+
+        let built = waiter.join().expect("join").expect("locked build");
+        assert_eq!(built, exe);
+    }
+
+    #[test]
+    fn unlocked_build_saves_last_good() {
+        let temp_dir = tempfile::tempdir().expect("temp dir");
+        let exe = temp_dir.path().join("demo-bin");
+        fs::write(&exe, "built").expect("write exe");
+        let cargo = temp_dir.path().join("cargo");
+        fs::write(
+            &cargo,
+            format!(
+                "#!/bin/sh\necho '{{\"reason\":\"compiler-artifact\",\"executable\":\"{}\"}}'\n",
+                exe.display()
+            ),
+        )
+        .expect("write fake cargo");
+        fs::set_permissions(&cargo, fs::Permissions::from_mode(0o755)).expect("chmod");
+
+        let last_good = temp_dir.path().join("last-good/demo");
+        let mut entry = native_entry("demo", Path::new("/a/bin/demo"));
+        entry.crate_root = temp_dir.path().to_path_buf();
+        entry.options.cargo = Some(cargo);
+        entry.options.last_good = Some(last_good.clone());
+        // A lock file that cannot be created falls back to an unlocked build.
+        for lock_path in [None, Some(exe.join("demo.lock"))] {
+            let _ = fs::remove_file(&last_good);
+            entry.options.lock_path = lock_path;
+            assert_eq!(locked_build(&entry), Ok(exe.clone()));
+            assert_eq!(fs::read_to_string(&last_good).expect("last good"), "built");
+        }
+    }
+
+    #[test]
+    fn format_utc_matches_date_output() {
+        assert_eq!(format_utc(UNIX_EPOCH), "1970-01-01 00:00:00 UTC");
+        let time = UNIX_EPOCH + std::time::Duration::from_secs(1_709_210_096);
+        assert_eq!(format_utc(time), "2024-02-29 12:34:56 UTC");
+    }
+
+    #[test]
+    fn save_last_good_copies_newer_builds() {
+        let temp_dir = tempfile::tempdir().expect("temp dir");
+        let exe = temp_dir.path().join("target/demo");
+        fs::create_dir_all(exe.parent().unwrap()).expect("create target");
+        fs::write(&exe, "v1").expect("write exe");
+
+        let last_good = temp_dir.path().join("last-good/demo");
+        let mut entry = native_entry("demo", Path::new("/a/bin/demo"));
+        entry.crate_root = temp_dir.path().to_path_buf();
+        entry.options.last_good = Some(last_good.clone());
+
+        save_last_good(&entry, &exe);
+        assert_eq!(fs::read_to_string(&last_good).expect("last good"), "v1");
+        assert!(last_good_matches(&entry, &last_good));
+        let info = fs::read_to_string(with_suffix(&last_good, "info")).expect("info");
+        assert!(info.starts_with("commit unknown, built "));
+
+        // An older artifact does not replace the saved copy.
+        fs::write(&exe, "v0").expect("write exe");
+        let old = SystemTime::now() - std::time::Duration::from_secs(3_600);
+        File::options()
+            .write(true)
+            .open(&exe)
+            .and_then(|file| file.set_modified(old))
+            .expect("set mtime");
+        save_last_good(&entry, &exe);
+        assert_eq!(fs::read_to_string(&last_good).expect("last good"), "v1");
+
+        // The same wrapper installed from another crate neither trusts nor
+        // keeps the copy it saved before, however old its own build is.
+        let mut other = entry.clone();
+        other.crate_root = temp_dir.path().join("other");
+        assert!(!last_good_matches(&other, &last_good));
+        save_last_good(&other, &exe);
+        assert_eq!(fs::read_to_string(&last_good).expect("last good"), "v0");
+        assert!(last_good_matches(&other, &last_good));
+    }
+
+    #[test]
+    fn parse_executable_returns_last_binary_artifact() {
+        let output = concat!(
+            "{\"reason\":\"compiler-artifact\",\"executable\":null}\n",
+            "{\"reason\":\"build-script-executed\"}\n",
+            "{\"reason\":\"compiler-artifact\",\"executable\":\"/repo/target/release/demo\"}\n",
+            "{\"reason\":\"build-finished\",\"success\":true}\n",
+        );
+        assert_eq!(
+            parse_executable(output),
+            Some(PathBuf::from("/repo/target/release/demo"))
+        );
+        assert_eq!(parse_executable(""), None);
+    }
+}
@@ -1,8 +1,23 @@
+pub mod atomic;
+pub mod cargo_config;
+pub mod checkout;
 pub mod cli;
+pub mod git;
 pub mod install;
+pub mod launcher;
+pub mod local;
 pub mod project;
+pub mod refresh;
+pub mod registry;
+pub mod relink;
+pub mod safety;
+pub mod snapshot;
 pub mod tui_select;
+pub mod uninstall;
+pub mod verify;
+pub mod watch;
 
+use std::ffi::OsString;
 use std::io::IsTerminal;
 use std::path::{Path, PathBuf};
 
@@ -11,17 +26,71 @@ pub struct InstallPlan {
     pub crate_root: PathBuf,
     pub manifest_path: PathBuf,
     pub bin_name: String,
+    /// File name of the wrapper; the binary's name unless `--name` is given.
+    pub name: String,
+    pub package: Option<String>,
     pub install_dir: PathBuf,
     pub wrapper_path: PathBuf,
+    /// What is at `wrapper_path` now, compared to what the plan would write.
+    pub existing: install::WrapperState,
+    /// Where a foreign file at `wrapper_path` is moved before installing;
+    /// only set with `--force-foreign`.
+    pub backup_path: Option<PathBuf>,
+    pub options: install::WrapperOptions,
     pub wrapper_contents: String,
-    pub warn_path_missing: bool,
+    /// Symlink target for native wrappers; `wrapper_contents` is unused then.
+    pub launcher: Option<PathBuf>,
+    pub registry_path: Option<PathBuf>,
+    /// Build with the wrapper's cargo invocation before installing it.
+    pub prebuild: bool,
+    /// Arguments for one run of the installed wrapper that must exit 0.
+    pub verify_args: Option<Vec<String>>,
+    /// Worktree to create or move before building, for `--snapshot`.
+    pub snapshot: Option<snapshot::Snapshot>,
+    /// Managed clone the crate was found in, for `--git`.
+    pub checkout: Option<checkout::Checkout>,
+    /// Extra names linked to the wrapper in `install_dir`.
+    pub aliases: Vec<String>,
+    /// Set for `--local`; `install_dir` is then its bin dir.
+    pub local: Option<local::LocalInstall>,
+    /// Pre-flight safety findings that did not refuse the install.
+    pub safety_warnings: Vec<String>,
+    pub path_status: install::PathStatus,
+}
+
+impl InstallPlan {
+    pub fn registry_entry(&self) -> registry::RegistryEntry {
+        registry::RegistryEntry {
+            name: self.name.clone(),
+            wrapper_path: self.wrapper_path.clone(),
+            crate_root: self.crate_root.clone(),
+            manifest_path: self.manifest_path.clone(),
+            bin_name: self.bin_name.clone(),
+            package: self.package.clone(),
+            options: self.options.clone(),
+            snapshot: self.snapshot.clone(),
+            checkout: self.checkout.clone(),
+            backup: self.backup_path.clone(),
+            hash: Some(match &self.launcher {
+                Some(launcher) => install::link_hash(launcher),
+                None => install::content_hash(self.wrapper_contents.as_bytes()),
+            }),
+            aliases: self.aliases.clone(),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct EnvSnapshot {
     pub home: Option<PathBuf>,
     pub xdg_bin_home: Option<PathBuf>,
+    pub xdg_cache_home: Option<PathBuf>,
+    pub xdg_data_home: Option<PathBuf>,
     pub path: Option<String>,
+    pub cargo: Option<PathBuf>,
+    pub cargo_home: Option<PathBuf>,
+    pub rustup_home: Option<PathBuf>,
+    pub current_exe: Option<PathBuf>,
 }
 
 impl EnvSnapshot {
@@ -29,17 +98,65 @@ impl EnvSnapshot {
         Self {
             home: std::env::var_os("HOME").map(PathBuf::from),
             xdg_bin_home: std::env::var_os("XDG_BIN_HOME").map(PathBuf::from),
+            xdg_cache_home: std::env::var_os("XDG_CACHE_HOME").map(PathBuf::from),
+            xdg_data_home: std::env::var_os("XDG_DATA_HOME").map(PathBuf::from),
             path: std::env::var("PATH").ok(),
+            cargo: std::env::var_os("CARGO").map(PathBuf::from),
+            cargo_home: std::env::var_os("CARGO_HOME").map(PathBuf::from),
+            rustup_home: std::env::var_os("RUSTUP_HOME").map(PathBuf::from),
+            current_exe: std::env::current_exe().ok(),
         }
     }
 }
 
 pub fn run() -> Result<(), String> {
-    let args = cli::parse_args(std::env::args()).map_err(|err| err.to_string())?;
+    let argv: Vec<OsString> = std::env::args_os().collect();
     let env = EnvSnapshot::capture();
-    let cwd = std::env::current_dir().map_err(|err| format!("failed to read cwd: {err}"))?;
-    let plan = make_plan(&args, &env, &cwd)?;
-    apply_plan(&plan, args.force)
+    if let Some(argv0) = argv.first()
+        && let Some(registry) = launcher::launcher_registry(argv0, &env)?
+    {
+        return launcher::run(argv0, &argv[1..], &env, &registry);
+    }
+
+    let args = cli::parse_args(argv).map_err(|err| err.to_string())?;
+    match &args.command {
+        Some(cli::Command::Watch) => return watch::run(&env),
+        Some(cli::Command::Update { name, rev }) => {
+            return update(&env, name.as_deref(), rev.as_deref());
+        }
+        Some(cli::Command::Refresh { all, force }) => return refresh::run(&env, *all, *force),
+        Some(cli::Command::Uninstall { wrapper, force }) => {
+            return uninstall::run(&env, wrapper, *force);
+        }
+        Some(cli::Command::Verify) => return verify::run(&env),
+        Some(cli::Command::Doctor) => return verify::doctor(&env),
+        Some(cli::Command::ExecProgram(exec_args)) => return launcher::exec_program(exec_args),
+        Some(cli::Command::Relink(_)) | None => {}
+    }
+    let mut cwd = std::env::current_dir().map_err(|err| format!("failed to read cwd: {err}"))?;
+    if let Some(cli::Command::Relink(relink_args)) = &args.command {
+        return relink::run(&env, &cwd, relink_args);
+    }
+    let checkout = match &args.git {
+        Some(url) => {
+            let checkouts_dir = checkout::checkouts_dir(&env).ok_or_else(|| {
+                "HOME is not set; cannot determine data directory for --git".to_string()
+            })?;
+            let url = checkout::normalize_url(url, &cwd)?;
+            let checkout = checkout::fetch(
+                &checkouts_dir,
+                &url,
+                args.branch.as_deref(),
+                args.rev.as_deref(),
+            )?;
+            cwd = checkout.dir.clone();
+            Some(checkout)
+        }
+        None => None,
+    };
+    let mut plan = make_plan(&args, &env, &cwd)?;
+    plan.checkout = checkout;
+    apply_plan(&plan, args.force || args.force_foreign)
 }
 
 pub fn make_plan(
@@ -48,42 +165,416 @@ pub fn make_plan(
     cwd: &Path,
 ) -> Result<InstallPlan, String> {
     let crate_root = project::find_crate_root(cwd)?;
-    let manifest_path = crate_root.join("Cargo.toml");
+    let mut manifest_path = crate_root.join("Cargo.toml");
 
     let bin_names = project::list_bins(&manifest_path)?;
     let bin_name = select_bin(args, &bin_names)?;
+    let package = project::package_name(&manifest_path);
+
+    let (workspace_root, config) = project::workspace_config(&manifest_path)?;
+    let env_prefix = match &args.export_context {
+        Some(prefix) => {
+            let prefix = prefix
+                .clone()
+                .or(config.env_prefix)
+                .unwrap_or_else(|| install::DEFAULT_ENV_PREFIX.to_string());
+            install::validate_env_prefix(&prefix)?;
+            Some(prefix)
+        }
+        None => None,
+    };
+
+    let local = if args.local {
+        Some(local::LocalInstall::new(
+            workspace_root.clone(),
+            config.local_bin_dir.as_deref(),
+            args.envrc,
+        ))
+    } else {
+        None
+    };
+    let install_dir = match &local {
+        Some(local) => local.bin_dir.clone(),
+        None => install::install_dir(env)
+            .ok_or_else(|| "HOME is not set; cannot determine install directory".to_string())?,
+    };
+    let name = args.name.clone().unwrap_or_else(|| bin_name.clone());
+    if name.is_empty() || name.contains('/') {
+        return Err(format!("invalid wrapper name '{name}'"));
+    }
+    let wrapper_path = install_dir.join(&name);
+    let cache_key = install::cache_key(&wrapper_path);
 
-    let install_dir = install::install_dir(env)
-        .ok_or_else(|| "HOME is not set; cannot determine install directory".to_string())?;
+    // Without an explicit pin, rustup picks up the repository's
+    // rust-toolchain file itself when the wrapper builds from the crate root,
+    // so edits to it apply and its components and targets are honored.
+    let toolchain = args.toolchain.clone();
+    if let Some(toolchain) = &toolchain {
+        install::validate_toolchain(toolchain)?;
+    }
+
+    let source_root = crate_root.clone();
+    let (crate_root, snapshot) = match &args.snapshot {
+        Some(rev) => {
+            let snapshots_dir = snapshot::snapshots_dir(env).ok_or_else(|| {
+                "HOME is not set; cannot determine data directory for --snapshot".to_string()
+            })?;
+            // Keyed like the caches, so snapshots of same-named binaries from
+            // different repositories or under different wrapper names never
+            // share a worktree.
+            let (snapshot, snapshot_root) =
+                snapshot::plan(&crate_root, rev, &snapshots_dir.join(&cache_key))?;
+            manifest_path = snapshot_root.join("Cargo.toml");
+            (snapshot_root, Some(snapshot))
+        }
+        None => (crate_root, None),
+    };
+    let repo_root = match &snapshot {
+        Some(snapshot) => snapshot.worktree.clone(),
+        None => project::git_toplevel(&crate_root).unwrap_or(workspace_root),
+    };
+    let verify_args = match &args.verify_args {
+        Some(verify_args) => Some(shlex::split(verify_args).ok_or_else(|| {
+            format!(
+                "invalid --verify-args '{verify_args}': unbalanced quotes or trailing backslash"
+            )
+        })?),
+        None => None,
+    };
+
+    let cache_dir = install::cache_dir(env);
+    let target_dir = if args.isolated_target_dir {
+        let cache_dir = cache_dir.as_ref().ok_or_else(|| {
+            "HOME is not set; cannot determine cache directory for --isolated-target-dir"
+                .to_string()
+        })?;
+        Some(cache_dir.join("targets").join(&cache_key))
+    } else {
+        None
+    };
 
-    let wrapper_path = install_dir.join(&bin_name);
-    let wrapper_contents = install::render_wrapper(&crate_root);
-    let warn_path_missing = !install::is_on_path(&install_dir, env.path.as_deref());
+    let last_good = if args.fallback_last_good {
+        let cache_dir = cache_dir.as_ref().ok_or_else(|| {
+            "HOME is not set; cannot determine cache directory for --fallback-last-good".to_string()
+        })?;
+        Some(cache_dir.join("last-good").join(&cache_key))
+    } else {
+        None
+    };
+
+    let options = install::WrapperOptions {
+        style: args.wrapper_style,
+        toolchain,
+        cargo: install::cargo_path(env),
+        cargo_home: env.cargo_home.clone(),
+        rustup_home: env.rustup_home.clone(),
+        sanitize_env: args
+            .sanitize_env
+            .unwrap_or_else(|| bin_name.starts_with("cargo-")),
+        target_dir,
+        lock_path: cache_dir
+            .as_ref()
+            .map(|dir| dir.join("locks").join(format!("{cache_key}.lock"))),
+        build_output: args.build_output,
+        last_good,
+        strict: args.strict,
+        preserve_argv0: args.preserve_argv0 || !args.aliases.is_empty(),
+        env_prefix,
+        repo_root: Some(repo_root),
+        // Native wrappers are the launcher themselves.
+        launcher: env
+            .current_exe
+            .clone()
+            .filter(|_| args.wrapper_style != install::WrapperStyle::Native),
+    };
+    if options.preserve_argv0 && options.style == install::WrapperStyle::Sh {
+        return Err(
+            "--preserve-argv0 and --alias need --wrapper-style bash or native; POSIX sh cannot set argv[0]"
+                .to_string(),
+        );
+    }
+    for alias in &args.aliases {
+        if alias.is_empty() || alias.contains('/') || *alias == name {
+            return Err(format!("invalid alias '{alias}'"));
+        }
+    }
+
+    let registry_path = registry::registry_path(env);
+    let (wrapper_contents, launcher) = match options.style {
+        install::WrapperStyle::Bash | install::WrapperStyle::Sh => (
+            install::render_wrapper(&crate_root, &bin_name, &options),
+            None,
+        ),
+        install::WrapperStyle::Native => {
+            if registry_path.is_none() {
+                return Err(
+                    "HOME is not set; native wrappers need the wrapper registry".to_string()
+                );
+            }
+            let launcher = env.current_exe.clone().ok_or_else(|| {
+                "cannot determine the cargo-dev-install executable for native wrappers".to_string()
+            })?;
+            (String::new(), Some(launcher))
+        }
+    };
+    let registered = registry_path.as_deref().is_some_and(|path| {
+        registry::Registry::load(path)
+            .is_ok_and(|registry| registry.find_by_path(&wrapper_path).is_some())
+    });
+    let existing = install::wrapper_state(
+        &wrapper_path,
+        &wrapper_contents,
+        launcher.as_deref(),
+        registered,
+    );
+    let backup_path = match existing {
+        install::WrapperState::Foreign if args.force_foreign => {
+            let data_dir = install::data_dir(env).ok_or_else(|| {
+                "HOME is not set; cannot determine data directory for --force-foreign".to_string()
+            })?;
+            Some(install::backup_path(
+                &data_dir.join("backups"),
+                &wrapper_path,
+            ))
+        }
+        _ => None,
+    };
+    let safety_warnings = safety::enforce(
+        safety::check(
+            &install_dir,
+            &source_root,
+            &wrapper_path,
+            existing,
+            safety::current_uid(),
+        ),
+        args.allow_unsafe_paths,
+    )?;
+    let path_status = install::is_on_path(
+        &install_dir,
+        env.path.as_deref(),
+        env.home.as_deref(),
+        Some(cwd),
+    );
 
     Ok(InstallPlan {
         crate_root,
         manifest_path,
         bin_name,
+        name,
+        package,
         install_dir,
         wrapper_path,
+        existing,
+        backup_path,
+        options,
         wrapper_contents,
-        warn_path_missing,
+        launcher,
+        registry_path,
+        prebuild: args.build,
+        verify_args,
+        snapshot,
+        checkout: None,
+        aliases: args.aliases.clone(),
+        local,
+        safety_warnings,
+        path_status,
     })
 }
 
 pub fn apply_plan(plan: &InstallPlan, force: bool) -> Result<(), String> {
-    install::write_wrapper(&plan.wrapper_path, &plan.wrapper_contents, force)
-        .map_err(|err| format!("failed to write wrapper: {err}"))?;
+    for warning in &plan.safety_warnings {
+        eprintln!("warning: {warning}");
+    }
+
+    // Refuse before any side effect: a refused install must neither check
+    // out a snapshot nor spend a build.
+    let path = plan.wrapper_path.display();
+    match (plan.existing, force) {
+        (install::WrapperState::Outdated, false) => {
+            return Err(format!(
+                "failed to write wrapper: {path} was installed by cargo-dev-install with different settings; use --force to update it"
+            ));
+        }
+        (install::WrapperState::Foreign, _) if plan.backup_path.is_none() => {
+            return Err(format!(
+                "failed to write wrapper: {path} exists and was not installed by cargo-dev-install; use --force-foreign to back it up and replace it"
+            ));
+        }
+        _ => {}
+    }
+    if let Some(snapshot) = &plan.snapshot {
+        snapshot::materialize(snapshot)?;
+    }
+    if plan.prebuild {
+        launcher::build(&plan.registry_entry())
+            .map_err(|err| format!("{err}; wrapper not installed"))?;
+    }
+    if let Some(local) = &plan.local {
+        local::ensure_gitignored(&local.bin_dir).map_err(|err| {
+            format!(
+                "failed to create {}: {err}",
+                local.bin_dir.join(".gitignore").display()
+            )
+        })?;
+    }
+    if plan.existing != install::WrapperState::UpToDate {
+        let write = || match &plan.launcher {
+            Some(launcher) => install::write_launcher_link(&plan.wrapper_path, launcher, force),
+            None => install::write_wrapper(&plan.wrapper_path, &plan.wrapper_contents, force),
+        };
+        match &plan.backup_path {
+            Some(backup_path) => {
+                install::replace_with_backup(&plan.wrapper_path, backup_path, write)?;
+            }
+            None => write().map_err(|err| format!("failed to write wrapper: {err}"))?,
+        }
+    }
+    for alias in &plan.aliases {
+        install::write_alias(&plan.wrapper_path, alias)?;
+    }
+    match plan.existing {
+        install::WrapperState::Missing => println!("installed {path}"),
+        install::WrapperState::UpToDate => println!("{path} is up to date"),
+        install::WrapperState::Outdated => println!("updated {path}"),
+        install::WrapperState::Foreign => match &plan.backup_path {
+            Some(backup_path) => {
+                println!(
+                    "replaced {path}; original saved to {}",
+                    backup_path.display()
+                );
+            }
+            None => println!("replaced {path}"),
+        },
+    }
+
+    if let Some(registry_path) = &plan.registry_path {
+        let _lock = registry::Registry::lock(registry_path)?;
+        let mut registry = registry::Registry::load(registry_path)?;
+        let mut entry = plan.registry_entry();
+        if let Some(previous) = registry.find_by_path(&entry.wrapper_path) {
+            // A reinstall over our own wrapper must not forget what to restore.
+            if entry.backup.is_none() {
+                entry.backup = previous.backup.clone();
+            }
+            for alias in &previous.aliases {
+                if !entry.aliases.contains(alias) {
+                    install::remove_alias(&entry.wrapper_path, alias)
+                        .map_err(|err| format!("failed to remove alias {alias}: {err}"))?;
+                }
+            }
+        }
+        registry.upsert(entry);
+        registry.save(registry_path)?;
+    }
+
+    if let Some(verify_args) = &plan.verify_args {
+        verify_wrapper(&plan.wrapper_path, verify_args)?;
+    }
 
-    if plan.warn_path_missing {
-        eprintln!("Warning: install directory is not on PATH");
-        eprintln!("Add it to your shell profile, e.g.:");
-        eprintln!("export PATH=\"{}:$PATH\"", plan.install_dir.display());
+    match &plan.local {
+        Some(local) if local.envrc => {
+            let dir = local.envrc_dir();
+            let added = local::add_to_envrc(&local.workspace_root, &dir).map_err(|err| {
+                format!(
+                    "failed to update {}: {err}",
+                    local.workspace_root.join(".envrc").display()
+                )
+            })?;
+            if added {
+                println!(
+                    "added `PATH_add {}` to .envrc; run `direnv allow` to enable it",
+                    dir.display()
+                );
+            }
+        }
+        Some(local) => {
+            if plan.path_status != install::PathStatus::Present {
+                eprintln!(
+                    "note: {} is only on PATH where you add it, e.g. with `PATH_add {}` in .envrc (see --envrc)",
+                    plan.install_dir.display(),
+                    local.envrc_dir().display()
+                );
+            }
+        }
+        None => {
+            if let Some(problem) = plan.path_status.problem() {
+                eprintln!("Warning: {problem}");
+                eprintln!("Add it to your shell profile, e.g.:");
+                eprintln!("export PATH=\"{}:$PATH\"", plan.install_dir.display());
+            }
+        }
     }
 
     Ok(())
 }
 
+/// Moves the sources of snapshot and `--git` wrappers (all, or the one called
+/// `name`) forward and rebuilds them so the next invocation starts warm.
+pub fn update(env: &EnvSnapshot, name: Option<&str>, rev: Option<&str>) -> Result<(), String> {
+    let registry_path = registry::registry_path(env)
+        .ok_or_else(|| "HOME is not set; cannot locate the wrapper registry".to_string())?;
+    let _lock = registry::Registry::lock(&registry_path)?;
+    let mut registry = registry::Registry::load(&registry_path)?;
+
+    let mut updated = 0;
+    let mut failed = Vec::new();
+    for entry in &mut registry.wrappers {
+        if name.is_some_and(|name| name != entry.name) {
+            continue;
+        }
+        let change = match (&mut entry.snapshot, &mut entry.checkout) {
+            (Some(snapshot), _) => snapshot::update(snapshot, rev),
+            (None, Some(checkout)) => checkout::update(checkout, rev),
+            (None, None) => continue,
+        };
+        updated += 1;
+        let result = change
+            .map_err(|err| format!("{}: {err}", entry.name))
+            .and_then(|change| {
+                println!("{}: {change}", entry.name);
+                launcher::locked_build(entry).map_err(String::from)
+            });
+        if let Err(err) = result {
+            eprintln!("{err}");
+            failed.push(entry.name.clone());
+        }
+    }
+
+    if updated == 0 {
+        return Err(match name {
+            Some(name) => format!("no snapshot or --git wrapper named '{name}' is registered"),
+            None => "no snapshot or --git wrappers registered".to_string(),
+        });
+    }
+    registry.save(&registry_path)?;
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("failed to update {}", failed.join(", ")))
+    }
+}
+
+fn verify_wrapper(wrapper_path: &Path, args: &[String]) -> Result<(), String> {
+    let invocation = std::iter::once(wrapper_path.display().to_string())
+        .chain(args.iter().cloned())
+        .collect::<Vec<_>>()
+        .join(" ");
+    let output = std::process::Command::new(wrapper_path)
+        .args(args)
+        .stdin(std::process::Stdio::null())
+        .output()
+        .map_err(|err| format!("failed to run `{invocation}`: {err}"))?;
+    if output.status.success() {
+        return Ok(());
+    }
+
+    eprint!("{}", String::from_utf8_lossy(&output.stderr));
+    Err(format!(
+        "wrapper installed, but verification failed: `{invocation}` exited with {}",
+        output.status
+    ))
+}
+
 fn select_bin(args: &cli::CliArgs, bin_names: &[String]) -> Result<String, String> {
     if bin_names.is_empty() {
         return Err("no binary targets found in Cargo.toml".to_string());
@@ -126,7 +617,13 @@ mod tests {
         EnvSnapshot {
             home: Some(home.to_path_buf()),
             xdg_bin_home: None,
+            xdg_cache_home: None,
+            xdg_data_home: None,
             path: Some(path_var.to_string()),
+            cargo: None,
+            cargo_home: None,
+            rustup_home: None,
+            current_exe: Some(PathBuf::from("/opt/cargo-dev-install")),
         }
     }
 
@@ -139,10 +636,7 @@ mod tests {
         );
         write_file(&dir.path().join("src/main.rs"), "fn main() {}\n");
 
-        let args = cli::CliArgs {
-            bin: None,
-            force: false,
-        };
+        let args = cli::CliArgs::default();
         let env = default_env(dir.path(), "/usr/bin");
 
         let plan = make_plan(&args, &env, dir.path()).expect("plan");
@@ -164,7 +658,7 @@ mod tests {
 
         let args = cli::CliArgs {
             bin: Some("beta".to_string()),
-            force: false,
+            ..Default::default()
         };
         let env = default_env(dir.path(), "/usr/bin");
 
@@ -181,13 +675,327 @@ mod tests {
         );
         write_file(&dir.path().join("src/main.rs"), "fn main() {}\n");
 
+        let args = cli::CliArgs::default();
+        let env = default_env(dir.path(), "/usr/bin");
+
+        let plan = make_plan(&args, &env, dir.path()).expect("plan");
+        assert_eq!(plan.path_status, install::PathStatus::Missing);
+    }
+
+    #[test]
+    fn make_plan_leaves_toolchain_file_to_rustup() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        write_file(
+            &dir.path().join("Cargo.toml"),
+            "[package]\nname = \"demo\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[[bin]]\nname = \"demo\"\npath = \"src/main.rs\"\n",
+        );
+        write_file(&dir.path().join("src/main.rs"), "fn main() {}\n");
+        write_file(
+            &dir.path().join("rust-toolchain.toml"),
+            "[toolchain]\nchannel = \"nightly\"\n",
+        );
+
+        let args = cli::CliArgs::default();
+        let env = default_env(dir.path(), "/usr/bin");
+
+        let plan = make_plan(&args, &env, dir.path()).expect("plan");
+        assert_eq!(plan.options.toolchain, None);
+        assert!(!plan.wrapper_contents.contains("RUSTUP_TOOLCHAIN=\""));
+    }
+
+    #[test]
+    fn make_plan_prefers_explicit_toolchain() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        write_file(
+            &dir.path().join("Cargo.toml"),
+            "[package]\nname = \"demo\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[[bin]]\nname = \"demo\"\npath = \"src/main.rs\"\n",
+        );
+        write_file(&dir.path().join("src/main.rs"), "fn main() {}\n");
+        write_file(&dir.path().join("rust-toolchain"), "nightly\n");
+
         let args = cli::CliArgs {
-            bin: None,
-            force: false,
+            toolchain: Some("stable".to_string()),
+            ..Default::default()
         };
         let env = default_env(dir.path(), "/usr/bin");
 
         let plan = make_plan(&args, &env, dir.path()).expect("plan");
-        assert!(plan.warn_path_missing);
+        assert_eq!(plan.options.toolchain, Some("stable".to_string()));
+    }
+
+    #[test]
+    fn make_plan_sanitizes_env_for_cargo_subcommands() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        write_file(
+            &dir.path().join("Cargo.toml"),
+            "[package]\nname = \"demo\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[[bin]]\nname = \"cargo-demo\"\npath = \"src/main.rs\"\n",
+        );
+        write_file(&dir.path().join("src/main.rs"), "fn main() {}\n");
+        let env = default_env(dir.path(), "/usr/bin");
+
+        let plan = make_plan(&cli::CliArgs::default(), &env, dir.path()).expect("plan");
+        assert!(plan.options.sanitize_env);
+
+        let args = cli::CliArgs {
+            sanitize_env: Some(false),
+            ..Default::default()
+        };
+        let plan = make_plan(&args, &env, dir.path()).expect("plan");
+        assert!(!plan.options.sanitize_env);
+    }
+
+    #[test]
+    fn make_plan_records_isolated_target_dir() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        write_file(
+            &dir.path().join("Cargo.toml"),
+            "[package]\nname = \"demo\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[[bin]]\nname = \"demo\"\npath = \"src/main.rs\"\n",
+        );
+        write_file(&dir.path().join("src/main.rs"), "fn main() {}\n");
+        let env = default_env(dir.path(), "/usr/bin");
+
+        let plan = make_plan(&cli::CliArgs::default(), &env, dir.path()).expect("plan");
+        let key = install::cache_key(&plan.wrapper_path);
+        assert!(key.starts_with("demo-"));
+        assert_eq!(plan.options.target_dir, None);
+        assert_eq!(
+            plan.options.lock_path,
+            Some(
+                dir.path()
+                    .join(".cache/cargo-dev-install/locks")
+                    .join(format!("{key}.lock"))
+            )
+        );
+
+        let args = cli::CliArgs {
+            isolated_target_dir: true,
+            ..Default::default()
+        };
+        let plan = make_plan(&args, &env, dir.path()).expect("plan");
+        assert_eq!(
+            plan.options.target_dir,
+            Some(
+                dir.path()
+                    .join(".cache/cargo-dev-install/targets")
+                    .join(&key)
+            )
+        );
+    }
+
+    #[test]
+    fn make_plan_records_last_good_cache() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        write_file(
+            &dir.path().join("Cargo.toml"),
+            "[package]\nname = \"demo\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[[bin]]\nname = \"demo\"\npath = \"src/main.rs\"\n",
+        );
+        write_file(&dir.path().join("src/main.rs"), "fn main() {}\n");
+
+        let args = cli::CliArgs {
+            fallback_last_good: true,
+            strict: true,
+            ..Default::default()
+        };
+        let env = default_env(dir.path(), "/usr/bin");
+
+        let plan = make_plan(&args, &env, dir.path()).expect("plan");
+        assert_eq!(
+            plan.options.last_good,
+            Some(
+                dir.path()
+                    .join(".cache/cargo-dev-install/last-good")
+                    .join(install::cache_key(&plan.wrapper_path))
+            )
+        );
+        assert!(plan.options.strict);
+    }
+
+    #[test]
+    fn make_plan_splits_verify_args_like_a_shell() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        write_file(
+            &dir.path().join("Cargo.toml"),
+            "[package]\nname = \"demo\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[[bin]]\nname = \"demo\"\npath = \"src/main.rs\"\n",
+        );
+        write_file(&dir.path().join("src/main.rs"), "fn main() {}\n");
+
+        let args = cli::CliArgs {
+            build: true,
+            verify_args: Some("--version  --name 'my tool' \"a b\"".to_string()),
+            ..Default::default()
+        };
+        let env = default_env(dir.path(), "/usr/bin");
+
+        let plan = make_plan(&args, &env, dir.path()).expect("plan");
+        assert!(plan.prebuild);
+        assert_eq!(
+            plan.verify_args,
+            Some(vec![
+                "--version".to_string(),
+                "--name".to_string(),
+                "my tool".to_string(),
+                "a b".to_string(),
+            ])
+        );
+
+        let args = cli::CliArgs {
+            verify_args: Some("--name 'my tool".to_string()),
+            ..Default::default()
+        };
+        let err = make_plan(&args, &env, dir.path()).expect_err("unbalanced quote");
+        assert!(err.contains("invalid --verify-args"));
+    }
+
+    #[test]
+    fn make_plan_native_style_links_launcher() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        write_file(
+            &dir.path().join("Cargo.toml"),
+            "[package]\nname = \"demo\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[[bin]]\nname = \"demo\"\npath = \"src/main.rs\"\n",
+        );
+        write_file(&dir.path().join("src/main.rs"), "fn main() {}\n");
+
+        let args = cli::CliArgs {
+            wrapper_style: install::WrapperStyle::Native,
+            ..Default::default()
+        };
+        let env = default_env(dir.path(), "/usr/bin");
+
+        let plan = make_plan(&args, &env, dir.path()).expect("plan");
+        assert_eq!(plan.launcher, Some(PathBuf::from("/opt/cargo-dev-install")));
+        assert!(plan.wrapper_contents.is_empty());
+        assert_eq!(
+            plan.registry_path,
+            Some(
+                dir.path()
+                    .join(".local/share/cargo-dev-install/registry.json")
+            )
+        );
+    }
+
+    #[test]
+    fn apply_plan_records_registry_entry() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        write_file(
+            &dir.path().join("Cargo.toml"),
+            "[package]\nname = \"demo\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[[bin]]\nname = \"demo\"\npath = \"src/main.rs\"\n",
+        );
+        write_file(&dir.path().join("src/main.rs"), "fn main() {}\n");
+        let env = default_env(dir.path(), "/usr/bin");
+
+        let plan = make_plan(&cli::CliArgs::default(), &env, dir.path()).expect("plan");
+        apply_plan(&plan, false).expect("apply");
+
+        let registry_path = plan.registry_path.as_deref().expect("registry path");
+        let registry = registry::Registry::load(registry_path).expect("registry");
+        let entry = registry
+            .find_by_path(&plan.wrapper_path)
+            .expect("registry entry");
+        assert_eq!(entry.crate_root, plan.crate_root);
+        assert_eq!(entry.options, plan.options);
+    }
+
+    #[test]
+    fn make_plan_local_uses_workspace_bin_dir() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        write_file(
+            &dir.path().join("Cargo.toml"),
+            "[package]\nname = \"demo\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[[bin]]\nname = \"demo\"\npath = \"src/main.rs\"\n\n[workspace]\n\n[workspace.metadata.dev-install]\nlocal-bin-dir = \"tools/bin\"\n",
+        );
+        write_file(&dir.path().join("src/main.rs"), "fn main() {}\n");
+        let env = default_env(dir.path(), "/usr/bin");
+        let args = cli::CliArgs {
+            local: true,
+            ..Default::default()
+        };
+
+        let plan = make_plan(&args, &env, dir.path()).expect("plan");
+        let local = plan.local.as_ref().expect("local");
+        assert_eq!(
+            local.workspace_root.canonicalize().unwrap(),
+            dir.path().canonicalize().unwrap()
+        );
+        assert_eq!(local.envrc_dir(), PathBuf::from("tools/bin"));
+        assert_eq!(plan.wrapper_path, local.bin_dir.join("demo"));
+    }
+
+    #[test]
+    fn make_plan_keeps_caches_of_same_named_local_wrappers_apart() {
+        let home = tempfile::tempdir().expect("home");
+        let env = default_env(home.path(), "/usr/bin");
+        let args = cli::CliArgs {
+            local: true,
+            isolated_target_dir: true,
+            fallback_last_good: true,
+            ..Default::default()
+        };
+
+        let plans: Vec<_> = ["a", "b"]
+            .iter()
+            .map(|repo| {
+                let root = home.path().join(repo);
+                write_file(
+                    &root.join("Cargo.toml"),
+                    "[package]\nname = \"demo\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[[bin]]\nname = \"demo\"\npath = \"src/main.rs\"\n\n[workspace]\n",
+                );
+                write_file(&root.join("src/main.rs"), "fn main() {}\n");
+                make_plan(&args, &env, &root).expect("plan")
+            })
+            .collect();
+        assert_ne!(plans[0].options.lock_path, plans[1].options.lock_path);
+        assert_ne!(plans[0].options.target_dir, plans[1].options.target_dir);
+        assert_ne!(plans[0].options.last_good, plans[1].options.last_good);
+    }
+
+    #[test]
+    fn apply_plan_refuses_before_building() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        write_file(
+            &dir.path().join("Cargo.toml"),
+            "[package]\nname = \"demo\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[[bin]]\nname = \"demo\"\npath = \"src/main.rs\"\n",
+        );
+        write_file(&dir.path().join("src/main.rs"), "fn main() { broken }\n");
+        let env = default_env(dir.path(), "/usr/bin");
+        let plan = make_plan(&cli::CliArgs::default(), &env, dir.path()).expect("plan");
+        apply_plan(&plan, false).expect("apply");
+
+        let args = cli::CliArgs {
+            toolchain: Some("nightly".to_string()),
+            build: true,
+            ..Default::default()
+        };
+        let plan = make_plan(&args, &env, dir.path()).expect("plan");
+        let err = apply_plan(&plan, false).expect_err("needs --force");
+        assert!(err.contains("use --force to update it"), "{err}");
+        assert!(!dir.path().join("target").exists());
+    }
+
+    #[test]
+    fn make_plan_reports_existing_wrapper_state() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        write_file(
+            &dir.path().join("Cargo.toml"),
+            "[package]\nname = \"demo\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[[bin]]\nname = \"demo\"\npath = \"src/main.rs\"\n",
+        );
+        write_file(&dir.path().join("src/main.rs"), "fn main() {}\n");
+        let env = default_env(dir.path(), "/usr/bin");
+        let args = cli::CliArgs::default();
+
+        let plan = make_plan(&args, &env, dir.path()).expect("plan");
+        assert_eq!(plan.existing, install::WrapperState::Missing);
+        apply_plan(&plan, false).expect("apply");
+
+        let plan = make_plan(&args, &env, dir.path()).expect("plan");
+        assert_eq!(plan.existing, install::WrapperState::UpToDate);
+        apply_plan(&plan, false).expect("no-op apply");
+
+        let args = cli::CliArgs {
+            toolchain: Some("nightly".to_string()),
+            ..Default::default()
+        };
+        let plan = make_plan(&args, &env, dir.path()).expect("plan");
+        assert_eq!(plan.existing, install::WrapperState::Outdated);
+        let err = apply_plan(&plan, false).expect_err("needs --force");
+        assert!(err.contains("use --force to update it"));
     }
 }
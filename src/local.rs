@@ -0,0 +1,120 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// A `--local` install into a bin dir inside the workspace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalInstall {
+    pub workspace_root: PathBuf,
+    pub bin_dir: PathBuf,
+    /// Add the bin dir to the workspace's `.envrc`.
+    pub envrc: bool,
+}
+
+impl LocalInstall {
+    /// `configured` is the project's `local-bin-dir`, relative to the
+    /// workspace root; the default is `.bin`.
+    pub fn new(workspace_root: PathBuf, configured: Option<&Path>, envrc: bool) -> Self {
+        let bin_dir = workspace_root.join(configured.unwrap_or(Path::new(".bin")));
+        Self {
+            workspace_root,
+            bin_dir,
+            envrc,
+        }
+    }
+
+    /// The bin dir as `.envrc` refers to it: relative to the workspace root
+    /// when it is inside it.
+    pub fn envrc_dir(&self) -> PathBuf {
+        self.bin_dir
+            .strip_prefix(&self.workspace_root)
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|_| self.bin_dir.clone())
+    }
+}
+
+/// Creates `bin_dir` with a `.gitignore` that ignores everything in it,
+/// itself included, so wrappers never show up in `git status`. Returns
+/// whether the `.gitignore` was created.
+pub fn ensure_gitignored(bin_dir: &Path) -> io::Result<bool> {
+    fs::create_dir_all(bin_dir)?;
+    match fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(bin_dir.join(".gitignore"))
+    {
+        Ok(mut file) => {
+            file.write_all(b"*\n")?;
+            Ok(true)
+        }
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+/// Appends `PATH_add <dir>` to `<workspace_root>/.envrc` unless an identical
+/// line is already there. Returns whether the file changed.
+pub fn add_to_envrc(workspace_root: &Path, dir: &Path) -> io::Result<bool> {
+    let envrc = workspace_root.join(".envrc");
+    let line = format!("PATH_add {}", dir.display());
+    let existing = match fs::read_to_string(&envrc) {
+        Ok(existing) => existing,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err),
+    };
+    if existing.lines().any(|existing| existing.trim() == line) {
+        return Ok(false);
+    }
+
+    let mut file = fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(&envrc)?;
+    if !existing.is_empty() && !existing.ends_with('\n') {
+        file.write_all(b"\n")?;
+    }
+    writeln!(file, "{line}")?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bin_dir_defaults_to_dot_bin() {
+        let local = LocalInstall::new(PathBuf::from("/ws"), None, false);
+        assert_eq!(local.bin_dir, PathBuf::from("/ws/.bin"));
+        assert_eq!(local.envrc_dir(), PathBuf::from(".bin"));
+
+        let local = LocalInstall::new(PathBuf::from("/ws"), Some(Path::new("tools/bin")), false);
+        assert_eq!(local.bin_dir, PathBuf::from("/ws/tools/bin"));
+        assert_eq!(local.envrc_dir(), PathBuf::from("tools/bin"));
+    }
+
+    #[test]
+    fn gitignore_is_written_once() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let bin_dir = dir.path().join(".bin");
+        assert!(ensure_gitignored(&bin_dir).expect("first"));
+        fs::write(bin_dir.join(".gitignore"), "*\n!keep\n").expect("edit");
+        assert!(!ensure_gitignored(&bin_dir).expect("second"));
+        assert_eq!(
+            fs::read_to_string(bin_dir.join(".gitignore")).unwrap(),
+            "*\n!keep\n"
+        );
+    }
+
+    #[test]
+    fn envrc_line_is_added_once() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        fs::write(dir.path().join(".envrc"), "use flake").expect("write envrc");
+
+        assert!(add_to_envrc(dir.path(), Path::new(".bin")).expect("add"));
+        assert!(!add_to_envrc(dir.path(), Path::new(".bin")).expect("again"));
+        assert_eq!(
+            fs::read_to_string(dir.path().join(".envrc")).unwrap(),
+            "use flake\nPATH_add .bin\n"
+        );
+    }
+}
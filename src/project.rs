@@ -1,6 +1,66 @@
 use cargo_metadata::{MetadataCommand, TargetKind};
+use serde::Deserialize;
 use std::path::{Path, PathBuf};
 
+/// Per-project settings from `[package.metadata.dev-install]`, falling back
+/// to `[workspace.metadata.dev-install]`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ProjectConfig {
+    /// Directory for `--local` wrappers, relative to the workspace root.
+    pub local_bin_dir: Option<PathBuf>,
+    /// Prefix of the repository context variables exported with
+    /// `--export-context`.
+    pub env_prefix: Option<String>,
+}
+
+impl ProjectConfig {
+    fn or(self, fallback: Self) -> Self {
+        Self {
+            local_bin_dir: self.local_bin_dir.or(fallback.local_bin_dir),
+            env_prefix: self.env_prefix.or(fallback.env_prefix),
+        }
+    }
+}
+
+/// Workspace root and project settings of the package at `manifest_path`.
+pub fn workspace_config(manifest_path: &Path) -> Result<(PathBuf, ProjectConfig), String> {
+    let metadata = MetadataCommand::new()
+        .manifest_path(manifest_path)
+        .no_deps()
+        .exec()
+        .map_err(|err| format!("failed to load cargo metadata: {err}"))?;
+
+    let parse = |value: &serde_json::Value| -> Result<ProjectConfig, String> {
+        match value.get("dev-install") {
+            Some(section) => ProjectConfig::deserialize(section).map_err(|err| {
+                format!(
+                    "invalid [metadata.dev-install] in {}: {err}",
+                    manifest_path.display()
+                )
+            }),
+            None => Ok(ProjectConfig::default()),
+        }
+    };
+    let workspace = parse(&metadata.workspace_metadata)?;
+    let manifest_path = manifest_path
+        .canonicalize()
+        .unwrap_or(manifest_path.to_path_buf());
+    let package = metadata
+        .packages
+        .iter()
+        .find(|package| package.manifest_path == manifest_path)
+        .or_else(|| metadata.root_package());
+    let package = match package {
+        Some(package) => parse(&package.metadata)?,
+        None => ProjectConfig::default(),
+    };
+    Ok((
+        metadata.workspace_root.into_std_path_buf(),
+        package.or(workspace),
+    ))
+}
+
 pub fn find_crate_root(cwd: &Path) -> Result<PathBuf, String> {
     let mut current = if cwd.is_absolute() {
         cwd.to_path_buf()
@@ -46,6 +106,23 @@ pub fn list_bins(manifest_path: &Path) -> Result<Vec<String>, String> {
         .collect())
 }
 
+/// Reads `package.name` from a manifest without invoking cargo, so it also
+/// works for many manifests at once and for crates that do not build.
+pub fn package_name(manifest_path: &Path) -> Option<String> {
+    let contents = std::fs::read_to_string(manifest_path).ok()?;
+    let manifest: toml::Table = contents.parse().ok()?;
+    let name = manifest.get("package")?.get("name")?.as_str()?;
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// Top level of the git repository containing `crate_root`, if any.
+pub fn git_toplevel(crate_root: &Path) -> Option<PathBuf> {
+    crate::git::run(crate_root, &["rev-parse", "--show-toplevel"])
+        .ok()
+        .filter(|toplevel| !toplevel.is_empty())
+        .map(PathBuf::from)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -58,6 +135,29 @@ mod tests {
         fs::write(path, contents).expect("write file");
     }
 
+    #[test]
+    fn reads_package_name_from_manifest() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let manifest = dir.path().join("Cargo.toml");
+        write_file(
+            &manifest,
+            "[workspace]\nname = \"not-this\"\n\n[package]\nversion = \"0.1.0\"\nname = \"demo\"\n",
+        );
+        assert_eq!(package_name(&manifest), Some("demo".to_string()));
+
+        write_file(
+            &manifest,
+            "[package]\nname = \"demo\" # the binary is demo too\n",
+        );
+        assert_eq!(package_name(&manifest), Some("demo".to_string()));
+        write_file(&manifest, "[package]\nname = 'demo'\n");
+        assert_eq!(package_name(&manifest), Some("demo".to_string()));
+
+        write_file(&manifest, "[workspace]\nmembers = [\"a\"]\n");
+        assert_eq!(package_name(&manifest), None);
+        assert_eq!(package_name(&dir.path().join("missing.toml")), None);
+    }
+
     #[test]
     fn find_crate_root_in_current_dir() {
         let dir = tempfile::tempdir().expect("tempdir");
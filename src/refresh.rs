@@ -0,0 +1,259 @@
+use crate::install::{self, TEMPLATE_VERSION, WrapperState, WrapperStyle};
+use crate::registry::{self, Registry, RegistryEntry};
+use std::fs;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    UpToDate,
+    /// Rewritten; carries the diff against the previous contents.
+    Refreshed(String),
+    /// The wrapper file was missing and has been written again.
+    Restored,
+    /// The wrapper no longer matches its checksum and `--force` was not given.
+    HandEdited,
+    /// Something this tool did not write, such as a script of the user's,
+    /// now sits at the wrapper path; it is never overwritten.
+    Foreign,
+}
+
+/// Re-renders one script wrapper from its recorded options. Without `all`,
+/// wrappers already on the current template are left alone, and files that
+/// are not ours are never touched. The recorded hash follows whatever this
+/// writes.
+pub fn refresh_entry(entry: &mut RegistryEntry, all: bool, force: bool) -> Result<Outcome, String> {
+    let contents = install::render_wrapper(&entry.crate_root, &entry.bin_name, &entry.options);
+    let hash = install::content_hash(contents.as_bytes());
+    let write = |entry: &mut RegistryEntry| {
+        install::write_wrapper(&entry.wrapper_path, &contents, true)
+            .map_err(|err| format!("failed to write {}: {err}", entry.wrapper_path.display()))?;
+        entry.hash = Some(hash.clone());
+        Ok::<_, String>(())
+    };
+
+    // Being registered does not make the file ours: the user may have put
+    // their own script (or a binary) there since.
+    match install::wrapper_state(&entry.wrapper_path, &contents, None, false) {
+        WrapperState::Missing => {
+            write(entry)?;
+            return Ok(Outcome::Restored);
+        }
+        WrapperState::UpToDate => {
+            entry.hash = Some(hash);
+            return Ok(Outcome::UpToDate);
+        }
+        WrapperState::Foreign => return Ok(Outcome::Foreign),
+        WrapperState::Outdated => {}
+    }
+    let existing = fs::read_to_string(&entry.wrapper_path)
+        .map_err(|err| format!("failed to read {}: {err}", entry.wrapper_path.display()))?;
+
+    match install::read_marker(&existing) {
+        Some(marker) if !marker.intact && !force => return Ok(Outcome::HandEdited),
+        Some(marker) if marker.template == TEMPLATE_VERSION && marker.intact && !all => {
+            return Ok(Outcome::UpToDate);
+        }
+        _ => {}
+    }
+
+    write(entry)?;
+    Ok(Outcome::Refreshed(diff(&existing, &contents)))
+}
+
+/// Line diff of `old` against `new` listing only removed and added lines.
+pub fn diff(old: &str, new: &str) -> String {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+
+    // lcs[i][j] is the length of the longest common subsequence of old[i..]
+    // and new[j..]; wrappers are short, so the quadratic table is fine.
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut out = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            out.push_str(&format!("-{}\n", old[i]));
+            i += 1;
+        } else {
+            out.push_str(&format!("+{}\n", new[j]));
+            j += 1;
+        }
+    }
+    out
+}
+
+pub fn run(env: &crate::EnvSnapshot, all: bool, force: bool) -> Result<(), String> {
+    let registry_path = registry::registry_path(env)
+        .ok_or_else(|| "HOME is not set; cannot locate the wrapper registry".to_string())?;
+    let _lock = Registry::lock(&registry_path)?;
+    let mut registry = Registry::load(&registry_path)?;
+    let before = registry.clone();
+
+    let mut skipped = 0;
+    let mut failed = Vec::new();
+    for entry in &mut registry.wrappers {
+        // Native wrappers are symlinks to this executable; nothing to render.
+        if entry.options.style == WrapperStyle::Native {
+            continue;
+        }
+
+        let outcome = match refresh_entry(entry, all, force) {
+            Ok(outcome) => outcome,
+            Err(err) => {
+                eprintln!("{}: {err}", entry.name);
+                failed.push(entry.name.clone());
+                continue;
+            }
+        };
+        let path = entry.wrapper_path.display();
+        match outcome {
+            Outcome::UpToDate => println!("{}: up to date", entry.name),
+            Outcome::Restored => println!("{}: restored missing {path}", entry.name),
+            Outcome::Refreshed(diff) => {
+                println!("--- {path}\n+++ {path}\n{diff}{}: refreshed", entry.name);
+            }
+            Outcome::HandEdited => {
+                eprintln!(
+                    "warning: {}: {path} was edited by hand; skipping (use --force to overwrite)",
+                    entry.name
+                );
+                skipped += 1;
+            }
+            Outcome::Foreign => {
+                eprintln!(
+                    "warning: {}: {path} was not written by cargo-dev-install; skipping (reinstall with --force-foreign to replace it)",
+                    entry.name
+                );
+                skipped += 1;
+            }
+        }
+    }
+
+    if registry != before {
+        registry.save(&registry_path)?;
+    }
+    if skipped > 0 {
+        eprintln!("{skipped} wrapper(s) skipped");
+    }
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("failed to refresh {}", failed.join(", ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::install::WrapperOptions;
+    use std::path::{Path, PathBuf};
+
+    fn entry(wrapper_path: &Path) -> RegistryEntry {
+        RegistryEntry {
+            name: "demo".to_string(),
+            wrapper_path: wrapper_path.to_path_buf(),
+            crate_root: PathBuf::from("/repo"),
+            manifest_path: PathBuf::from("/repo/Cargo.toml"),
+            bin_name: "demo".to_string(),
+            package: None,
+            options: WrapperOptions::default(),
+            snapshot: None,
+            checkout: None,
+            backup: None,
+            hash: None,
+            aliases: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn diff_lists_removed_and_added_lines() {
+        assert_eq!(diff("a\nb\nc\n", "a\nB\nc\nd\n"), "-b\n+B\n+d\n");
+        assert_eq!(diff("same\n", "same\n"), "");
+    }
+
+    #[test]
+    fn refreshes_outdated_and_skips_hand_edited_wrappers() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let mut entry = entry(&dir.path().join("demo"));
+        let current = install::render_wrapper(&entry.crate_root, &entry.bin_name, &entry.options);
+
+        assert_eq!(
+            refresh_entry(&mut entry, false, false),
+            Ok(Outcome::Restored)
+        );
+        assert_eq!(
+            refresh_entry(&mut entry, false, false),
+            Ok(Outcome::UpToDate)
+        );
+
+        let outdated = current.replace(
+            &format!("template={TEMPLATE_VERSION} "),
+            &format!("template={} ", TEMPLATE_VERSION - 1),
+        );
+        fs::write(&entry.wrapper_path, &outdated).expect("write outdated");
+        let Ok(Outcome::Refreshed(changes)) = refresh_entry(&mut entry, false, false) else {
+            panic!("expected refresh");
+        };
+        assert!(changes.starts_with("-# cargo-dev-install template="));
+        assert_eq!(fs::read_to_string(&entry.wrapper_path).unwrap(), current);
+
+        fs::write(
+            &entry.wrapper_path,
+            current.replace("--release", "--offline"),
+        )
+        .expect("edit");
+        assert_eq!(
+            refresh_entry(&mut entry, true, false),
+            Ok(Outcome::HandEdited)
+        );
+        assert!(matches!(
+            refresh_entry(&mut entry, true, true),
+            Ok(Outcome::Refreshed(_))
+        ));
+        assert_eq!(fs::read_to_string(&entry.wrapper_path).unwrap(), current);
+    }
+
+    #[test]
+    fn refreshes_unversioned_wrappers() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let mut entry = entry(&dir.path().join("demo"));
+        fs::write(
+            &entry.wrapper_path,
+            "#!/usr/bin/env bash\nset -euo pipefail\n\nREPO=\"/repo\"\nexec cargo run --quiet --release --manifest-path \"$REPO/Cargo.toml\" -- \"$@\"\n",
+        )
+        .expect("write");
+
+        assert!(matches!(
+            refresh_entry(&mut entry, false, false),
+            Ok(Outcome::Refreshed(_))
+        ));
+    }
+
+    #[test]
+    fn never_overwrites_foreign_files() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let mut entry = entry(&dir.path().join("demo"));
+        for contents in [
+            &b"#!/bin/sh\necho mine\n"[..],
+            &[0x7f, b'E', b'L', b'F', 0xff],
+        ] {
+            fs::write(&entry.wrapper_path, contents).expect("write");
+            for force in [false, true] {
+                assert_eq!(refresh_entry(&mut entry, true, force), Ok(Outcome::Foreign));
+            }
+            assert_eq!(fs::read(&entry.wrapper_path).unwrap(), contents);
+        }
+    }
+}
@@ -0,0 +1,217 @@
+use crate::atomic::TempFile;
+use crate::checkout::Checkout;
+use crate::install::WrapperOptions;
+use crate::snapshot::Snapshot;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+/// A wrapper installed by this tool, with everything needed to rebuild it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegistryEntry {
+    pub name: String,
+    pub wrapper_path: PathBuf,
+    pub crate_root: PathBuf,
+    pub manifest_path: PathBuf,
+    pub bin_name: String,
+    /// Package name, used by `relink --scan` to find a moved crate.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub package: Option<String>,
+    #[serde(default)]
+    pub options: WrapperOptions,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snapshot: Option<Snapshot>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checkout: Option<Checkout>,
+    /// The foreign file `--force-foreign` replaced; `uninstall` restores it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backup: Option<PathBuf>,
+    /// `install::wrapper_hash` of the wrapper as this tool last wrote it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// Extra names installed next to the wrapper as symlinks to it.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Registry {
+    #[serde(default)]
+    pub wrappers: Vec<RegistryEntry>,
+}
+
+pub fn registry_path(env: &crate::EnvSnapshot) -> Option<PathBuf> {
+    crate::install::data_dir(env).map(|dir| dir.join("registry.json"))
+}
+
+impl Registry {
+    /// Takes an exclusive lock on `<path>.lock`, waiting for other installs,
+    /// updates and uninstalls to finish. Hold it from `load` through `save`
+    /// so concurrent changes are not lost; dropping the file releases it.
+    pub fn lock(path: &Path) -> Result<File, String> {
+        let lock_path = crate::launcher::with_suffix(path, "lock");
+        if let Some(parent) = lock_path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|err| format!("failed to create {}: {err}", parent.display()))?;
+        }
+        let lock = File::options()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_path)
+            .map_err(|err| format!("failed to open {}: {err}", lock_path.display()))?;
+        lock.lock()
+            .map_err(|err| format!("failed to lock {}: {err}", lock_path.display()))?;
+        Ok(lock)
+    }
+
+    /// Loads the registry, treating a missing file as an empty registry.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Self::default());
+            }
+            Err(err) => return Err(format!("failed to read {}: {err}", path.display())),
+        };
+
+        serde_json::from_str(&contents)
+            .map_err(|err| format!("failed to parse {}: {err}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|err| format!("failed to create {}: {err}", parent.display()))?;
+        }
+
+        let contents = serde_json::to_string_pretty(self)
+            .map_err(|err| format!("failed to serialize registry: {err}"))?;
+        TempFile::create(path, (contents + "\n").as_bytes(), 0o644)
+            .and_then(|temp| temp.persist(path, true))
+            .map_err(|err| format!("failed to write {}: {err}", path.display()))
+    }
+
+    /// Adds the entry, replacing any entry for the same wrapper path.
+    pub fn upsert(&mut self, entry: RegistryEntry) {
+        match self
+            .wrappers
+            .iter_mut()
+            .find(|existing| existing.wrapper_path == entry.wrapper_path)
+        {
+            Some(existing) => *existing = entry,
+            None => self.wrappers.push(entry),
+        }
+    }
+
+    pub fn remove(&mut self, wrapper_path: &Path) -> Option<RegistryEntry> {
+        let index = self
+            .wrappers
+            .iter()
+            .position(|entry| entry.wrapper_path == wrapper_path)?;
+        Some(self.wrappers.remove(index))
+    }
+
+    pub fn find_by_path(&self, wrapper_path: &Path) -> Option<&RegistryEntry> {
+        self.wrappers
+            .iter()
+            .find(|entry| entry.wrapper_path == wrapper_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, wrapper_path: &str) -> RegistryEntry {
+        RegistryEntry {
+            name: name.to_string(),
+            wrapper_path: PathBuf::from(wrapper_path),
+            crate_root: PathBuf::from("/repo"),
+            manifest_path: PathBuf::from("/repo/Cargo.toml"),
+            bin_name: name.to_string(),
+            package: None,
+            options: WrapperOptions::default(),
+            snapshot: None,
+            checkout: None,
+            backup: None,
+            hash: None,
+            aliases: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn lock_waits_for_the_holder() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("data/registry.json");
+        let held = Registry::lock(&path).expect("lock");
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let waiter = std::thread::spawn({
+            let path = path.clone();
+            move || {
+                let _lock = Registry::lock(&path).expect("lock after release");
+                sender.send(()).expect("send");
+            }
+        });
+        let wait = std::time::Duration::from_millis(200);
+        assert!(receiver.recv_timeout(wait).is_err());
+        drop(held);
+        receiver
+            .recv_timeout(std::time::Duration::from_secs(10))
+            .expect("waiter takes the lock");
+        waiter.join().expect("join");
+    }
+
+    #[test]
+    fn load_missing_registry_is_empty() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let registry = Registry::load(&dir.path().join("registry.json")).expect("load");
+        assert!(registry.wrappers.is_empty());
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("nested/registry.json");
+
+        let mut registry = Registry::default();
+        registry.upsert(entry("demo", "/bin/demo"));
+        registry.save(&path).expect("save");
+
+        let loaded = Registry::load(&path).expect("load");
+        assert_eq!(loaded, registry);
+    }
+
+    #[test]
+    fn upsert_replaces_same_wrapper_path() {
+        let mut registry = Registry::default();
+        registry.upsert(entry("demo", "/bin/demo"));
+        registry.upsert(entry("other", "/bin/other"));
+
+        let mut replacement = entry("demo", "/bin/demo");
+        replacement.crate_root = PathBuf::from("/moved");
+        registry.upsert(replacement);
+
+        assert_eq!(registry.wrappers.len(), 2);
+        let found = registry
+            .find_by_path(Path::new("/bin/demo"))
+            .expect("entry");
+        assert_eq!(found.crate_root, PathBuf::from("/moved"));
+
+        let removed = registry.remove(Path::new("/bin/demo")).expect("removed");
+        assert_eq!(removed.name, "demo");
+        assert!(registry.find_by_path(Path::new("/bin/demo")).is_none());
+        assert!(registry.remove(Path::new("/bin/demo")).is_none());
+    }
+
+    #[test]
+    fn load_reports_invalid_json() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("registry.json");
+        fs::write(&path, "not json").expect("write");
+
+        let err = Registry::load(&path).expect_err("expected error");
+        assert!(err.contains("failed to parse"));
+    }
+}
@@ -0,0 +1,475 @@
+use crate::atomic::TempFile;
+use crate::install::{self, WrapperStyle};
+use crate::project;
+use crate::registry::{self, Registry, RegistryEntry};
+use crate::snapshot;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// A registered crate that moved from `from` to `to`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Move {
+    pub name: String,
+    pub wrapper_path: PathBuf,
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+/// Replaces the leading `old` components of `path` with `new`.
+fn rebase(path: &Path, old: &Path, new: &Path) -> Option<PathBuf> {
+    let rest = path.strip_prefix(old).ok()?;
+    Some(if rest.as_os_str().is_empty() {
+        new.to_path_buf()
+    } else {
+        new.join(rest)
+    })
+}
+
+/// Makes a prefix given on the command line absolute and comparable with
+/// the registered paths. The old location usually no longer exists, so the
+/// path is normalized lexically and only its deepest existing ancestor is
+/// canonicalized.
+fn resolve(cwd: &Path, path: &Path) -> PathBuf {
+    let mut normal = PathBuf::new();
+    for component in cwd.join(path).components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normal.pop();
+            }
+            component => normal.push(component),
+        }
+    }
+    normal
+        .ancestors()
+        .find_map(|ancestor| {
+            let real = ancestor.canonicalize().ok()?;
+            let rest = normal.strip_prefix(ancestor).ok()?;
+            Some(if rest.as_os_str().is_empty() {
+                real
+            } else {
+                real.join(rest)
+            })
+        })
+        .unwrap_or(normal)
+}
+
+/// Retargets every entry whose crate (or snapshot source repository) lives
+/// under `old` to the same place under `new`. Wrappers under `old`, such as
+/// `--local` installs in the repository's `.bin`, moved along with it.
+pub fn relink_prefix(registry: &mut Registry, old: &Path, new: &Path) -> Vec<Move> {
+    let mut moves = Vec::new();
+    for entry in &mut registry.wrappers {
+        // Aliases link to the wrapper by file name and `.envrc` names the bin
+        // dir relative to the workspace, so both still hold after the move.
+        let wrapper_from = rebase(&entry.wrapper_path, old, new)
+            .map(|path| std::mem::replace(&mut entry.wrapper_path, path));
+        let found = moves.len();
+        if let Some(snapshot) = &mut entry.snapshot
+            && let Some(repo) = rebase(&snapshot.repo, old, new)
+        {
+            moves.push(Move {
+                name: entry.name.clone(),
+                wrapper_path: entry.wrapper_path.clone(),
+                from: std::mem::replace(&mut snapshot.repo, repo.clone()),
+                to: repo,
+            });
+        }
+        if let Some(crate_root) = rebase(&entry.crate_root, old, new) {
+            moves.push(retarget(entry, crate_root));
+        }
+        if let Some(from) = wrapper_from
+            && moves.len() == found
+        {
+            moves.push(Move {
+                name: entry.name.clone(),
+                wrapper_path: entry.wrapper_path.clone(),
+                from,
+                to: entry.wrapper_path.clone(),
+            });
+        }
+    }
+    moves
+}
+
+/// Finds crates under `dir` for entries whose crate root no longer exists,
+/// matching them by package name. Returns the moves and the entries that
+/// could not be matched unambiguously.
+pub fn relink_scan(registry: &mut Registry, dir: &Path) -> (Vec<Move>, Vec<String>) {
+    let mut by_package: HashMap<String, Vec<PathBuf>> = HashMap::new();
+    find_crates(dir, &mut by_package);
+
+    let mut moves = Vec::new();
+    let mut unmatched = Vec::new();
+    for entry in &mut registry.wrappers {
+        if entry.manifest_path.is_file() || entry.snapshot.is_some() || entry.checkout.is_some() {
+            continue;
+        }
+        let package = entry.package.clone().or_else(|| {
+            entry
+                .crate_root
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+        });
+        let candidates = package
+            .as_ref()
+            .and_then(|package| by_package.get(package))
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        match candidates {
+            [crate_root] => moves.push(retarget(entry, crate_root.clone())),
+            [] => unmatched.push(format!("{}: no crate found", entry.name)),
+            _ => unmatched.push(format!(
+                "{}: several crates match: {}",
+                entry.name,
+                candidates
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+        }
+    }
+    (moves, unmatched)
+}
+
+fn retarget(entry: &mut RegistryEntry, crate_root: PathBuf) -> Move {
+    entry.manifest_path = crate_root.join("Cargo.toml");
+    // Keep the repository root the same number of levels above the crate.
+    if let Some(repo_root) = &mut entry.options.repo_root
+        && let Ok(inside) = entry.crate_root.strip_prefix(&*repo_root)
+        && let Some(moved) = crate_root.ancestors().nth(inside.components().count())
+    {
+        *repo_root = moved.to_path_buf();
+    }
+    Move {
+        name: entry.name.clone(),
+        wrapper_path: entry.wrapper_path.clone(),
+        from: std::mem::replace(&mut entry.crate_root, crate_root.clone()),
+        to: crate_root,
+    }
+}
+
+fn find_crates(dir: &Path, by_package: &mut HashMap<String, Vec<PathBuf>>) {
+    if let Some(package) = project::package_name(&dir.join("Cargo.toml")) {
+        by_package
+            .entry(package)
+            .or_default()
+            .push(dir.to_path_buf());
+    }
+
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let skip = name.to_string_lossy().starts_with('.') || name == "target";
+        if !skip && entry.file_type().is_ok_and(|kind| kind.is_dir()) {
+            find_crates(&entry.path(), by_package);
+        }
+    }
+}
+
+/// Re-renders the script wrappers of the moved entries and saves the
+/// registry. All new wrappers are staged in temporary files before the first
+/// one is replaced, so a wrapper that cannot be written aborts the relink
+/// before anything changed. Replacing them can still fail part way; the
+/// saved registry then records the wrappers already replaced and keeps the
+/// entries of the others as in `previous`, the registry before the relink.
+pub fn apply(
+    registry: &mut Registry,
+    previous: &Registry,
+    registry_path: &Path,
+    moves: &[Move],
+) -> Result<(), String> {
+    let mut staged = Vec::new();
+    for (index, entry) in registry.wrappers.iter_mut().enumerate() {
+        if !moves
+            .iter()
+            .any(|change| change.wrapper_path == entry.wrapper_path)
+        {
+            continue;
+        }
+        if !matches!(entry.options.style, WrapperStyle::Bash | WrapperStyle::Sh) {
+            continue;
+        }
+
+        let contents = install::render_wrapper(&entry.crate_root, &entry.bin_name, &entry.options);
+        // Dropping `staged` on error removes the temp files written so far.
+        let temp = TempFile::create(&entry.wrapper_path, contents.as_bytes(), 0o755)
+            .map_err(|err| format!("failed to write {}: {err}", entry.wrapper_path.display()))?;
+        entry.hash = Some(install::content_hash(contents.as_bytes()));
+        staged.push((index, temp));
+    }
+
+    let mut staged = staged.into_iter();
+    let mut failed = None;
+    for (index, temp) in staged.by_ref() {
+        let wrapper_path = &registry.wrappers[index].wrapper_path;
+        if let Err(err) = temp.persist(wrapper_path, true) {
+            failed = Some((
+                index,
+                format!("failed to write {}: {err}", wrapper_path.display()),
+            ));
+            break;
+        }
+    }
+    let Some((index, err)) = failed else {
+        return registry.save(registry_path);
+    };
+    // The remaining temp files are removed as they are dropped here.
+    for index in std::iter::once(index).chain(staged.map(|(index, _)| index)) {
+        registry.wrappers[index] = previous.wrappers[index].clone();
+    }
+    registry.save(registry_path)?;
+    Err(err)
+}
+
+pub fn run(
+    env: &crate::EnvSnapshot,
+    cwd: &Path,
+    args: &crate::cli::RelinkArgs,
+) -> Result<(), String> {
+    let registry_path = registry::registry_path(env)
+        .ok_or_else(|| "HOME is not set; cannot locate the wrapper registry".to_string())?;
+    let _lock = Registry::lock(&registry_path)?;
+    let mut registry = Registry::load(&registry_path)?;
+    let previous = registry.clone();
+
+    let (moves, unmatched) = match (&args.scan, &args.old_prefix, &args.new_prefix) {
+        (Some(dir), _, _) => relink_scan(&mut registry, &resolve(cwd, dir)),
+        (None, Some(old), Some(new)) => (
+            relink_prefix(&mut registry, &resolve(cwd, old), &resolve(cwd, new)),
+            Vec::new(),
+        ),
+        _ => return Err("relink needs <OLD_PREFIX> <NEW_PREFIX> or --scan <DIR>".to_string()),
+    };
+    for message in &unmatched {
+        eprintln!("warning: {message}");
+    }
+    if moves.is_empty() {
+        return Err("no registered wrappers matched".to_string());
+    }
+
+    apply(&mut registry, &previous, &registry_path, &moves)?;
+    // The worktree's `.git` file names the repository by absolute path.
+    for (entry, before) in registry.wrappers.iter().zip(&previous.wrappers) {
+        if let (Some(snapshot), Some(before)) = (&entry.snapshot, &before.snapshot)
+            && snapshot.repo != before.repo
+            && let Err(err) = snapshot::repair(snapshot)
+        {
+            eprintln!("warning: {}: failed to repair worktree: {err}", entry.name);
+        }
+    }
+    for change in &moves {
+        println!(
+            "{}: {} -> {}",
+            change.name,
+            change.from.display(),
+            change.to.display()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::install::WrapperOptions;
+
+    fn entry(name: &str, crate_root: &Path) -> RegistryEntry {
+        RegistryEntry {
+            name: name.to_string(),
+            wrapper_path: PathBuf::from("/bin").join(name),
+            crate_root: crate_root.to_path_buf(),
+            manifest_path: crate_root.join("Cargo.toml"),
+            bin_name: name.to_string(),
+            package: Some(name.to_string()),
+            options: WrapperOptions::default(),
+            snapshot: None,
+            checkout: None,
+            backup: None,
+            hash: None,
+            aliases: Vec::new(),
+        }
+    }
+
+    fn write_manifest(crate_root: &Path, package: &str) {
+        fs::create_dir_all(crate_root).expect("create crate");
+        fs::write(
+            crate_root.join("Cargo.toml"),
+            format!("[package]\nname = \"{package}\"\n"),
+        )
+        .expect("write manifest");
+    }
+
+    #[test]
+    fn relink_prefix_matches_whole_components() {
+        let mut registry = Registry {
+            wrappers: vec![
+                entry("foo", Path::new("/home/u/src/foo")),
+                entry("nested", Path::new("/home/u/src/ws/nested")),
+                entry("other", Path::new("/home/u/src-old/other")),
+            ],
+        };
+
+        let moves = relink_prefix(
+            &mut registry,
+            Path::new("/home/u/src"),
+            Path::new("/home/u/work"),
+        );
+        assert_eq!(moves.len(), 2);
+        assert_eq!(
+            registry.wrappers[0].manifest_path,
+            PathBuf::from("/home/u/work/foo/Cargo.toml")
+        );
+        assert_eq!(
+            registry.wrappers[1].crate_root,
+            PathBuf::from("/home/u/work/ws/nested")
+        );
+        assert_eq!(
+            registry.wrappers[2].crate_root,
+            PathBuf::from("/home/u/src-old/other")
+        );
+    }
+
+    #[test]
+    fn relink_keeps_repo_root_above_the_crate() {
+        let mut entry = entry("nested", Path::new("/old/ws/crates/nested"));
+        entry.options.repo_root = Some(PathBuf::from("/old/ws"));
+        let mut registry = Registry {
+            wrappers: vec![entry],
+        };
+
+        relink_prefix(&mut registry, Path::new("/old"), Path::new("/new"));
+        assert_eq!(
+            registry.wrappers[0].options.repo_root,
+            Some(PathBuf::from("/new/ws"))
+        );
+    }
+
+    #[test]
+    fn relink_prefix_moves_wrappers_inside_the_repository() {
+        let mut local = entry("tool", Path::new("/old/repo"));
+        local.wrapper_path = PathBuf::from("/old/repo/.bin/tool");
+        let mut elsewhere = entry("other", Path::new("/src/other"));
+        elsewhere.wrapper_path = PathBuf::from("/old/repo/.bin/other");
+        let mut registry = Registry {
+            wrappers: vec![local, elsewhere],
+        };
+
+        let moves = relink_prefix(&mut registry, Path::new("/old"), Path::new("/new"));
+        assert_eq!(
+            moves,
+            vec![
+                Move {
+                    name: "tool".to_string(),
+                    wrapper_path: PathBuf::from("/new/repo/.bin/tool"),
+                    from: PathBuf::from("/old/repo"),
+                    to: PathBuf::from("/new/repo"),
+                },
+                Move {
+                    name: "other".to_string(),
+                    wrapper_path: PathBuf::from("/new/repo/.bin/other"),
+                    from: PathBuf::from("/old/repo/.bin/other"),
+                    to: PathBuf::from("/new/repo/.bin/other"),
+                },
+            ]
+        );
+        assert_eq!(
+            registry.wrappers[0].wrapper_path,
+            PathBuf::from("/new/repo/.bin/tool")
+        );
+        assert_eq!(registry.wrappers[1].crate_root, PathBuf::from("/src/other"));
+    }
+
+    #[test]
+    fn apply_records_only_the_wrappers_it_replaced() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let bin = dir.path().join("bin");
+        let registry_path = dir.path().join("registry.json");
+        let mut first = entry("first", Path::new("/old/first"));
+        first.wrapper_path = bin.join("first");
+        let mut second = entry("second", Path::new("/old/second"));
+        second.wrapper_path = bin.join("second");
+        // A non-empty directory cannot be replaced by the staged wrapper.
+        fs::create_dir_all(second.wrapper_path.join("blocked")).expect("create blocker");
+        let previous = Registry {
+            wrappers: vec![first, second],
+        };
+
+        let mut registry = previous.clone();
+        let moves = relink_prefix(&mut registry, Path::new("/old"), Path::new("/new"));
+        let err = apply(&mut registry, &previous, &registry_path, &moves)
+            .expect_err("second wrapper cannot be written");
+        assert!(err.contains("second"), "{err}");
+
+        let saved = Registry::load(&registry_path).expect("load");
+        assert_eq!(saved.wrappers[0].crate_root, PathBuf::from("/new/first"));
+        assert!(saved.wrappers[0].hash.is_some());
+        assert_eq!(saved.wrappers[1], previous.wrappers[1]);
+        assert!(
+            fs::read_to_string(bin.join("first"))
+                .expect("read first")
+                .contains("/new/first")
+        );
+        let mut left: Vec<_> = fs::read_dir(&bin)
+            .expect("read bin")
+            .map(|entry| entry.expect("entry").file_name())
+            .collect();
+        left.sort();
+        assert_eq!(left, ["first", "second"]);
+    }
+
+    #[test]
+    fn resolve_normalizes_prefixes_that_no_longer_exist() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let real = dir.path().canonicalize().expect("canonicalize");
+        let cwd = dir.path().join("work");
+        fs::create_dir(&cwd).expect("create work");
+
+        assert_eq!(resolve(&cwd, Path::new("../src")), real.join("src"));
+        assert_eq!(resolve(&cwd, Path::new("./a/../b/")), real.join("work/b"));
+        assert_eq!(
+            resolve(&cwd, Path::new("/old/./x/..")),
+            PathBuf::from("/old")
+        );
+        assert_eq!(resolve(&cwd, Path::new("..")), real);
+    }
+
+    #[test]
+    fn relink_scan_finds_moved_crates_by_package_name() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let present = dir.path().join("present");
+        write_manifest(&present, "present");
+        write_manifest(&dir.path().join("work/foo"), "foo");
+        write_manifest(&dir.path().join("work/a/dup"), "dup");
+        write_manifest(&dir.path().join("work/b/dup"), "dup");
+        write_manifest(&dir.path().join("work/foo/target/package/foo"), "foo");
+
+        let mut registry = Registry {
+            wrappers: vec![
+                entry("foo", &dir.path().join("src/foo")),
+                entry("dup", &dir.path().join("src/dup")),
+                entry("present", &present),
+                entry("gone", &dir.path().join("src/gone")),
+            ],
+        };
+
+        let (moves, unmatched) = relink_scan(&mut registry, dir.path());
+        assert_eq!(
+            moves,
+            vec![Move {
+                name: "foo".to_string(),
+                wrapper_path: PathBuf::from("/bin/foo"),
+                from: dir.path().join("src/foo"),
+                to: dir.path().join("work/foo"),
+            }]
+        );
+        assert_eq!(registry.wrappers[0].crate_root, dir.path().join("work/foo"));
+        assert_eq!(unmatched.len(), 2);
+        assert!(unmatched[0].starts_with("dup: several crates match"));
+        assert_eq!(unmatched[1], "gone: no crate found");
+    }
+}
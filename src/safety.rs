@@ -0,0 +1,217 @@
+use crate::install::WrapperState;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+/// A problem found before installing. Fatal issues refuse the install unless
+/// `--allow-unsafe-paths` is given.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Issue {
+    pub message: String,
+    pub fatal: bool,
+}
+
+fn issue(fatal: bool, message: String) -> Issue {
+    Issue { message, fatal }
+}
+
+/// Checks that nobody but `uid` (or root) can change what the wrapper runs:
+/// the install directory, the repository whose `build.rs` the wrapper builds,
+/// and a symlink already sitting at the wrapper path.
+pub fn check(
+    install_dir: &Path,
+    repo: &Path,
+    wrapper_path: &Path,
+    existing: WrapperState,
+    uid: u32,
+) -> Vec<Issue> {
+    let mut issues = Vec::new();
+
+    if let Ok(metadata) = fs::metadata(install_dir) {
+        let dir = install_dir.display();
+        let mode = metadata.mode();
+        if mode & 0o002 != 0 {
+            issues.push(issue(
+                true,
+                format!("install directory {dir} is world-writable"),
+            ));
+        } else if mode & 0o020 != 0 {
+            issues.push(issue(
+                false,
+                format!("install directory {dir} is group-writable"),
+            ));
+        }
+        if !trusted_owner(metadata.uid(), uid) {
+            issues.push(issue(
+                true,
+                format!("install directory {dir} is owned by uid {}", metadata.uid()),
+            ));
+        }
+    }
+
+    if let Ok(metadata) = fs::metadata(repo)
+        && !trusted_owner(metadata.uid(), uid)
+    {
+        issues.push(issue(
+            true,
+            format!(
+                "repository {} is owned by uid {}; its build scripts would run as you",
+                repo.display(),
+                metadata.uid()
+            ),
+        ));
+    }
+
+    if existing == WrapperState::Foreign
+        && wrapper_path
+            .symlink_metadata()
+            .is_ok_and(|metadata| metadata.file_type().is_symlink())
+    {
+        issues.push(issue(
+            true,
+            format!(
+                "{} is a symlink not created by cargo-dev-install",
+                wrapper_path.display()
+            ),
+        ));
+    }
+
+    issues
+}
+
+fn trusted_owner(owner: u32, uid: u32) -> bool {
+    owner == uid || owner == 0
+}
+
+/// Fails with every fatal issue unless `allow_unsafe`; otherwise returns the
+/// messages to print as warnings.
+pub fn enforce(issues: Vec<Issue>, allow_unsafe: bool) -> Result<Vec<String>, String> {
+    let fatal: Vec<&str> = issues
+        .iter()
+        .filter(|issue| issue.fatal)
+        .map(|issue| issue.message.as_str())
+        .collect();
+    if !fatal.is_empty() && !allow_unsafe {
+        return Err(format!(
+            "refusing to install: {}; use --allow-unsafe-paths to install anyway",
+            fatal.join("; ")
+        ));
+    }
+    Ok(issues.into_iter().map(|issue| issue.message).collect())
+}
+
+pub fn current_uid() -> u32 {
+    // SAFETY: geteuid has no preconditions and cannot fail.
+    unsafe { libc::geteuid() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn set_mode(path: &Path, mode: u32) {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode)).expect("chmod");
+    }
+
+    #[test]
+    fn flags_writable_install_dirs() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let bin = dir.path().join("bin");
+        fs::create_dir(&bin).expect("create bin");
+        let uid = current_uid();
+        let check = || {
+            check(
+                &bin,
+                dir.path(),
+                &bin.join("demo"),
+                WrapperState::Missing,
+                uid,
+            )
+        };
+
+        set_mode(&bin, 0o755);
+        assert!(check().is_empty());
+
+        set_mode(&bin, 0o775);
+        let issues = check();
+        assert_eq!(issues.len(), 1);
+        assert!(!issues[0].fatal);
+        assert!(issues[0].message.contains("group-writable"));
+
+        set_mode(&bin, 0o777);
+        let issues = check();
+        assert!(issues[0].fatal);
+        assert!(issues[0].message.contains("world-writable"));
+    }
+
+    #[test]
+    fn flags_paths_owned_by_another_user() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let mut uid = current_uid() + 1;
+        if uid == 1 {
+            // Root-owned paths are trusted, so as root hand the directory to
+            // someone else instead.
+            std::os::unix::fs::chown(dir.path(), Some(12345), None).expect("chown");
+            uid = 0;
+        }
+
+        let issues = check(
+            dir.path(),
+            dir.path(),
+            &dir.path().join("demo"),
+            WrapperState::Missing,
+            uid,
+        );
+        assert_eq!(issues.len(), 2);
+        assert!(issues.iter().all(|issue| issue.fatal));
+        assert!(issues[1].message.contains("build scripts would run as you"));
+    }
+
+    #[test]
+    fn flags_foreign_symlinks_at_the_wrapper_path() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let wrapper_path = dir.path().join("demo");
+        std::os::unix::fs::symlink("/usr/bin/true", &wrapper_path).expect("symlink");
+        let uid = current_uid();
+
+        let issues = check(
+            dir.path(),
+            dir.path(),
+            &wrapper_path,
+            WrapperState::Foreign,
+            uid,
+        );
+        assert_eq!(issues.len(), 1);
+        assert!(issues[0].message.contains("is a symlink"));
+        assert!(
+            check(
+                dir.path(),
+                dir.path(),
+                &wrapper_path,
+                WrapperState::Outdated,
+                uid
+            )
+            .is_empty()
+        );
+    }
+
+    #[test]
+    fn enforce_refuses_fatal_issues_without_override() {
+        let issues = vec![
+            issue(false, "group".to_string()),
+            issue(true, "world".to_string()),
+        ];
+        let err = enforce(issues.clone(), false).expect_err("refused");
+        assert!(err.contains("world"));
+        assert!(!err.contains("group"));
+        assert_eq!(
+            enforce(issues, true),
+            Ok(vec!["group".to_string(), "world".to_string()])
+        );
+        assert_eq!(
+            enforce(vec![issue(false, "group".to_string())], false),
+            Ok(vec!["group".to_string()])
+        );
+    }
+}
@@ -0,0 +1,241 @@
+use crate::git;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// A wrapper that builds from a managed `git worktree` pinned to a commit
+/// instead of from the live checkout.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// Repository the snapshot was taken from; revisions resolve against it.
+    pub repo: PathBuf,
+    /// Revision as given by the user, re-resolved by `update`.
+    pub rev: String,
+    /// Full hash of the commit checked out in the worktree.
+    pub commit: String,
+    pub worktree: PathBuf,
+}
+
+/// Directory holding snapshot worktrees:
+/// `$XDG_DATA_HOME/cargo-dev-install/snapshots`.
+pub fn snapshots_dir(env: &crate::EnvSnapshot) -> Option<PathBuf> {
+    crate::install::data_dir(env).map(|dir| dir.join("snapshots"))
+}
+
+/// Resolves `rev` in the repository containing `crate_root` and returns the
+/// snapshot together with the crate root inside `worktree`.
+pub fn plan(crate_root: &Path, rev: &str, worktree: &Path) -> Result<(Snapshot, PathBuf), String> {
+    let repo = git::run(crate_root, &["rev-parse", "--show-toplevel"])
+        .map(PathBuf::from)
+        .map_err(|_| format!("{} is not in a git repository", crate_root.display()))?;
+    let prefix = git::run(crate_root, &["rev-parse", "--show-prefix"])?;
+    let commit = resolve_rev(&repo, rev)?;
+    let snapshot_root = match prefix.trim_end_matches('/') {
+        "" => worktree.to_path_buf(),
+        prefix => worktree.join(prefix),
+    };
+
+    let snapshot = Snapshot {
+        repo,
+        rev: rev.to_string(),
+        commit,
+        worktree: worktree.to_path_buf(),
+    };
+    Ok((snapshot, snapshot_root))
+}
+
+pub fn resolve_rev(repo: &Path, rev: &str) -> Result<String, String> {
+    git::run(
+        repo,
+        &[
+            "rev-parse",
+            "--verify",
+            "--quiet",
+            &format!("{rev}^{{commit}}"),
+        ],
+    )
+    .map_err(|_| format!("unknown revision '{rev}' in {}", repo.display()))
+}
+
+/// Creates the worktree, or moves an existing one to the snapshot commit.
+/// Local changes in the worktree are discarded; it is managed by this tool.
+pub fn materialize(snapshot: &Snapshot) -> Result<(), String> {
+    if snapshot.worktree.join(".git").exists() {
+        git::run(
+            &snapshot.worktree,
+            &[
+                "checkout",
+                "--quiet",
+                "--force",
+                "--detach",
+                &snapshot.commit,
+            ],
+        )?;
+        return Ok(());
+    }
+
+    if let Some(parent) = snapshot.worktree.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|err| format!("failed to create {}: {err}", parent.display()))?;
+    }
+    let worktree = snapshot.worktree.to_string_lossy();
+    git::run(
+        &snapshot.repo,
+        &[
+            "worktree",
+            "add",
+            "--quiet",
+            "--detach",
+            &worktree,
+            &snapshot.commit,
+        ],
+    )?;
+    Ok(())
+}
+
+/// Moves the snapshot to the latest commit of its recorded revision, or to
+/// `rev` when given, and describes the change.
+pub fn update(snapshot: &mut Snapshot, rev: Option<&str>) -> Result<String, String> {
+    if let Some(rev) = rev {
+        snapshot.rev = rev.to_string();
+    }
+    let previous = std::mem::replace(
+        &mut snapshot.commit,
+        resolve_rev(&snapshot.repo, &snapshot.rev)?,
+    );
+    materialize(snapshot)?;
+    Ok(if previous == snapshot.commit {
+        format!("already at {}", short(&snapshot.commit))
+    } else {
+        format!(
+            "updated {} -> {}",
+            short(&previous),
+            short(&snapshot.commit)
+        )
+    })
+}
+
+/// Removes the worktree and unregisters it from the source repository. A
+/// worktree whose repository is gone is simply deleted.
+pub fn remove(snapshot: &Snapshot) -> Result<(), String> {
+    if !snapshot.worktree.exists() {
+        return Ok(());
+    }
+    let worktree = snapshot.worktree.to_string_lossy();
+    match git::run(
+        &snapshot.repo,
+        &["worktree", "remove", "--force", &worktree],
+    ) {
+        Ok(_) => Ok(()),
+        Err(_) if !snapshot.repo.exists() => std::fs::remove_dir_all(&snapshot.worktree)
+            .map_err(|err| format!("failed to remove {worktree}: {err}")),
+        Err(err) => Err(err),
+    }
+}
+
+/// Reconnects the worktree and its source repository after either moved;
+/// each records the other's absolute path.
+pub fn repair(snapshot: &Snapshot) -> Result<(), String> {
+    let worktree = snapshot.worktree.to_string_lossy();
+    git::run(&snapshot.repo, &["worktree", "repair", &worktree]).map(|_| ())
+}
+
+fn short(commit: &str) -> &str {
+    &commit[..commit.len().min(12)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::process::{Command, Stdio};
+
+    fn run_git(dir: &Path, args: &[&str]) {
+        let status = Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .stdout(Stdio::null())
+            .status()
+            .expect("run git");
+        assert!(status.success(), "git {args:?}");
+    }
+
+    fn commit_file(repo: &Path, path: &str, contents: &str) -> String {
+        let file = repo.join(path);
+        fs::create_dir_all(file.parent().expect("parent")).expect("create dir");
+        fs::write(file, contents).expect("write file");
+        run_git(repo, &["add", "-A"]);
+        run_git(repo, &["commit", "--quiet", "-m", contents]);
+        git::run(repo, &["rev-parse", "HEAD"]).expect("head")
+    }
+
+    #[test]
+    fn plan_maps_crate_root_into_worktree() {
+        let repo = tempfile::tempdir().expect("repo");
+        run_git(repo.path(), &["init", "--quiet"]);
+        let first = commit_file(repo.path(), "tools/demo/Cargo.toml", "one");
+        commit_file(repo.path(), "tools/demo/Cargo.toml", "two");
+
+        let worktree = Path::new("/data/snapshots/demo");
+        let (snapshot, crate_root) =
+            plan(&repo.path().join("tools/demo"), "HEAD~1", worktree).expect("plan");
+        assert_eq!(snapshot.commit, first);
+        assert_eq!(snapshot.rev, "HEAD~1");
+        assert_eq!(crate_root, worktree.join("tools/demo"));
+
+        let err = plan(repo.path(), "no-such-branch", worktree).expect_err("unknown rev");
+        assert!(err.contains("unknown revision 'no-such-branch'"));
+    }
+
+    #[test]
+    fn materialize_creates_and_moves_worktree() {
+        let repo = tempfile::tempdir().expect("repo");
+        run_git(repo.path(), &["init", "--quiet"]);
+        let first = commit_file(repo.path(), "file.txt", "one");
+        let second = commit_file(repo.path(), "file.txt", "two");
+
+        let data = tempfile::tempdir().expect("data");
+        let worktree = data.path().join("snapshots/demo");
+        let (mut snapshot, _) = plan(repo.path(), &first, &worktree).expect("plan");
+        materialize(&snapshot).expect("create worktree");
+        assert_eq!(
+            fs::read_to_string(worktree.join("file.txt")).unwrap(),
+            "one"
+        );
+
+        fs::write(worktree.join("file.txt"), "local edit").expect("edit");
+        snapshot.commit = second;
+        materialize(&snapshot).expect("move worktree");
+        assert_eq!(
+            fs::read_to_string(worktree.join("file.txt")).unwrap(),
+            "two"
+        );
+        assert_eq!(
+            fs::read_to_string(repo.path().join("file.txt")).unwrap(),
+            "two"
+        );
+    }
+
+    #[test]
+    fn repair_reconnects_worktree_after_repo_moved() {
+        let dir = tempfile::tempdir().expect("dir");
+        let repo = dir.path().join("src/repo");
+        fs::create_dir_all(&repo).expect("create repo");
+        run_git(&repo, &["init", "--quiet"]);
+        let first = commit_file(&repo, "file.txt", "one");
+
+        let worktree = dir.path().join("snapshots/demo");
+        let (mut snapshot, _) = plan(&repo, &first, &worktree).expect("plan");
+        materialize(&snapshot).expect("create worktree");
+
+        let moved = dir.path().join("work/repo");
+        fs::create_dir_all(moved.parent().unwrap()).expect("create work");
+        fs::rename(&repo, &moved).expect("move repo");
+        assert!(git::run(&worktree, &["rev-parse", "HEAD"]).is_err());
+
+        snapshot.repo = moved;
+        repair(&snapshot).expect("repair");
+        assert_eq!(git::run(&worktree, &["rev-parse", "HEAD"]).unwrap(), first);
+    }
+}
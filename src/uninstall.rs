@@ -0,0 +1,239 @@
+use crate::install;
+use crate::launcher::with_suffix;
+use crate::registry::{self, Registry, RegistryEntry};
+use crate::snapshot;
+use crate::verify::{self, Problem};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Picks the registered wrapper called `wrapper`, or installed at that path
+/// when it contains a `/`.
+pub fn find<'a>(registry: &'a Registry, wrapper: &str) -> Result<&'a RegistryEntry, String> {
+    if wrapper.contains('/') {
+        return registry
+            .find_by_path(Path::new(wrapper))
+            .ok_or_else(|| format!("no wrapper is registered at {wrapper}"));
+    }
+
+    let matches: Vec<&RegistryEntry> = registry
+        .wrappers
+        .iter()
+        .filter(|entry| entry.name == wrapper)
+        .collect();
+    match matches.as_slice() {
+        [entry] => Ok(entry),
+        [] => Err(format!("no wrapper named '{wrapper}' is registered")),
+        _ => Err(format!(
+            "several wrappers are named '{wrapper}'; pass one of their paths: {}",
+            matches
+                .iter()
+                .map(|entry| entry.wrapper_path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+/// Removes the wrapper and puts back the file it replaced, if any. A wrapper
+/// that no longer matches its recorded hash may be someone else's file by
+/// now and is only removed with `force`.
+pub fn uninstall(entry: &RegistryEntry, force: bool) -> Result<(), String> {
+    let path = entry.wrapper_path.display();
+    if !force && verify::check_entry(entry)? == Some(Problem::Modified) {
+        return Err(format!(
+            "{path} was modified since it was installed; pass --force to remove it anyway"
+        ));
+    }
+    match fs::remove_file(&entry.wrapper_path) {
+        Ok(()) => println!("removed {path}"),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => return Err(format!("failed to remove {path}: {err}")),
+    }
+    for alias in &entry.aliases {
+        install::remove_alias(&entry.wrapper_path, alias)
+            .map_err(|err| format!("failed to remove alias {alias}: {err}"))?;
+    }
+
+    if let Some(backup) = &entry.backup {
+        if backup.symlink_metadata().is_err() {
+            eprintln!(
+                "warning: backup {} of {path} is missing; nothing to restore",
+                backup.display()
+            );
+            return Ok(());
+        }
+        install::move_file(backup, &entry.wrapper_path)
+            .map_err(|err| format!("failed to restore {path}: {err}"))?;
+        println!("restored {path} from {}", backup.display());
+    }
+    Ok(())
+}
+
+/// Removes what the wrapper's builds left behind: its isolated target dir,
+/// last good build, lock files and snapshot worktree. The wrapper is gone by
+/// then, so failures are only warnings.
+pub fn remove_build_state(entry: &RegistryEntry) {
+    let options = &entry.options;
+    let mut files: Vec<PathBuf> = Vec::new();
+    if let Some(lock_path) = &options.lock_path {
+        files.push(lock_path.clone());
+        files.extend(["notice", "exe"].map(|suffix| with_suffix(lock_path, suffix)));
+    }
+    if let Some(last_good) = &options.last_good {
+        files.push(last_good.clone());
+        files.extend(["info", "source", "tmp"].map(|suffix| with_suffix(last_good, suffix)));
+    }
+    for file in &files {
+        match fs::remove_file(file) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => {
+                eprintln!("warning: failed to remove {}: {err}", file.display());
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(target_dir) = &options.target_dir {
+        match fs::remove_dir_all(target_dir) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => {
+                eprintln!("warning: failed to remove {}: {err}", target_dir.display());
+            }
+            _ => {}
+        }
+    }
+    if let Some(snapshot) = &entry.snapshot
+        && let Err(err) = snapshot::remove(snapshot)
+    {
+        eprintln!("warning: {err}");
+    }
+}
+
+pub fn run(env: &crate::EnvSnapshot, wrapper: &str, force: bool) -> Result<(), String> {
+    let registry_path = registry::registry_path(env)
+        .ok_or_else(|| "HOME is not set; cannot locate the wrapper registry".to_string())?;
+    let _lock = Registry::lock(&registry_path)?;
+    let mut registry = Registry::load(&registry_path)?;
+
+    let entry = find(&registry, wrapper)?.clone();
+    uninstall(&entry, force)?;
+    remove_build_state(&entry);
+    registry.remove(&entry.wrapper_path);
+    registry.save(&registry_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::install::WrapperOptions;
+    use std::path::PathBuf;
+
+    fn entry(name: &str, wrapper_path: &Path) -> RegistryEntry {
+        RegistryEntry {
+            name: name.to_string(),
+            wrapper_path: wrapper_path.to_path_buf(),
+            crate_root: PathBuf::from("/repo"),
+            manifest_path: PathBuf::from("/repo/Cargo.toml"),
+            bin_name: name.to_string(),
+            package: None,
+            options: WrapperOptions::default(),
+            snapshot: None,
+            checkout: None,
+            backup: None,
+            hash: None,
+            aliases: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn find_requires_a_path_for_ambiguous_names() {
+        let registry = Registry {
+            wrappers: vec![
+                entry("demo", Path::new("/a/demo")),
+                entry("demo", Path::new("/b/demo")),
+                entry("other", Path::new("/a/other")),
+            ],
+        };
+
+        assert_eq!(
+            find(&registry, "other").expect("unique").wrapper_path,
+            PathBuf::from("/a/other")
+        );
+        assert!(
+            find(&registry, "demo")
+                .expect_err("ambiguous")
+                .contains("/a/demo, /b/demo")
+        );
+        assert_eq!(
+            find(&registry, "/b/demo").expect("by path").wrapper_path,
+            PathBuf::from("/b/demo")
+        );
+        assert!(find(&registry, "missing").is_err());
+    }
+
+    #[test]
+    fn uninstall_restores_backup() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let wrapper_path = dir.path().join("bin/demo");
+        let backup = dir.path().join("backups/demo");
+        fs::create_dir_all(wrapper_path.parent().unwrap()).expect("create bin");
+        fs::create_dir_all(backup.parent().unwrap()).expect("create backups");
+        fs::write(&wrapper_path, "ours").expect("write wrapper");
+        fs::write(&backup, "theirs").expect("write backup");
+
+        let mut entry = entry("demo", &wrapper_path);
+        entry.backup = Some(backup.clone());
+        uninstall(&entry, false).expect("uninstall");
+
+        assert_eq!(fs::read_to_string(&wrapper_path).expect("read"), "theirs");
+        assert!(!backup.exists());
+    }
+
+    #[test]
+    fn uninstall_refuses_modified_wrappers_without_force() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let wrapper_path = dir.path().join("demo");
+        fs::write(&wrapper_path, "ours").expect("write wrapper");
+        let mut entry = entry("demo", &wrapper_path);
+        entry.hash = Some(install::content_hash(b"ours"));
+
+        fs::write(&wrapper_path, "someone else's").expect("replace wrapper");
+        let err = uninstall(&entry, false).expect_err("modified");
+        assert!(err.contains("--force"), "{err}");
+        assert!(wrapper_path.exists());
+
+        uninstall(&entry, true).expect("forced uninstall");
+        assert!(!wrapper_path.exists());
+    }
+
+    #[test]
+    fn remove_build_state_clears_cache_entries() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let mut entry = entry("demo", &dir.path().join("bin/demo"));
+        let target_dir = dir.path().join("targets/demo-key");
+        let last_good = dir.path().join("last-good/demo-key");
+        let lock_path = dir.path().join("locks/demo-key.lock");
+        fs::create_dir_all(target_dir.join("release")).expect("create target");
+        fs::create_dir_all(last_good.parent().unwrap()).expect("create last-good");
+        fs::create_dir_all(lock_path.parent().unwrap()).expect("create locks");
+        for file in [
+            &last_good,
+            &with_suffix(&last_good, "info"),
+            &with_suffix(&last_good, "source"),
+            &lock_path,
+            &with_suffix(&lock_path, "exe"),
+        ] {
+            fs::write(file, "").expect("write cache file");
+        }
+        entry.options.target_dir = Some(target_dir.clone());
+        entry.options.last_good = Some(last_good.clone());
+        entry.options.lock_path = Some(lock_path.clone());
+
+        remove_build_state(&entry);
+        for cache in ["targets", "last-good", "locks"] {
+            let left: Vec<_> = fs::read_dir(dir.path().join(cache))
+                .expect("read cache dir")
+                .collect();
+            assert!(left.is_empty(), "{cache} not empty");
+        }
+    }
+}
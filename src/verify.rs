@@ -0,0 +1,299 @@
+use crate::install;
+use crate::registry::{self, Registry, RegistryEntry};
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// The wrapper no longer matches the hash recorded when it was written.
+    Modified,
+    Missing,
+    /// A wrapper generated by this tool that the registry does not know.
+    Unexpected,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub path: PathBuf,
+    pub problem: Problem,
+}
+
+impl std::fmt::Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let problem = match self.problem {
+            Problem::Modified => "modified",
+            Problem::Missing => "missing",
+            Problem::Unexpected => "unexpected",
+        };
+        write!(f, "{problem}: {}", self.path.display())
+    }
+}
+
+/// Checks one registered wrapper against its recorded hash. Entries written
+/// before hashes were recorded fall back to the checksum in the marker line.
+pub fn check_entry(entry: &RegistryEntry) -> Result<Option<Problem>, String> {
+    let path = entry.wrapper_path.display();
+    let actual = match install::wrapper_hash(&entry.wrapper_path) {
+        Ok(actual) => actual,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Some(Problem::Missing)),
+        Err(err) => return Err(format!("failed to read {path}: {err}")),
+    };
+
+    let intact = match &entry.hash {
+        Some(recorded) => *recorded == actual,
+        None => fs::read_to_string(&entry.wrapper_path)
+            .ok()
+            .and_then(|contents| install::read_marker(&contents))
+            .is_none_or(|marker| marker.intact),
+    };
+    Ok((!intact).then_some(Problem::Modified))
+}
+
+/// Checks that an alias is still the symlink to the wrapper that
+/// [`install::write_alias`] created.
+fn check_alias(entry: &RegistryEntry, alias: &str) -> Option<Problem> {
+    match fs::read_link(entry.wrapper_path.with_file_name(alias)) {
+        Ok(target) if Some(target.as_os_str()) == entry.wrapper_path.file_name() => None,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Some(Problem::Missing),
+        _ => Some(Problem::Modified),
+    }
+}
+
+/// Verifies every registered wrapper and its aliases, then looks through `install_dirs` and
+/// the directories holding registered wrappers for generated wrappers that
+/// are not registered.
+pub fn verify(registry: &Registry, install_dirs: &[PathBuf]) -> Result<Vec<Finding>, String> {
+    let mut findings = Vec::new();
+    for entry in &registry.wrappers {
+        if let Some(problem) = check_entry(entry)? {
+            findings.push(Finding {
+                path: entry.wrapper_path.clone(),
+                problem,
+            });
+        }
+        for alias in &entry.aliases {
+            if let Some(problem) = check_alias(entry, alias) {
+                findings.push(Finding {
+                    path: entry.wrapper_path.with_file_name(alias),
+                    problem,
+                });
+            }
+        }
+    }
+
+    let registered: BTreeSet<&Path> = registry
+        .wrappers
+        .iter()
+        .map(|entry| entry.wrapper_path.as_path())
+        .collect();
+    let dirs: BTreeSet<&Path> = install_dirs
+        .iter()
+        .map(PathBuf::as_path)
+        .chain(registered.iter().filter_map(|path| path.parent()))
+        .collect();
+    for dir in dirs {
+        let Ok(entries) = fs::read_dir(dir) else {
+            continue;
+        };
+        let mut unexpected: Vec<PathBuf> = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| !registered.contains(path.as_path()) && is_generated(path))
+            .collect();
+        unexpected.sort();
+        findings.extend(unexpected.into_iter().map(|path| Finding {
+            path,
+            problem: Problem::Unexpected,
+        }));
+    }
+    Ok(findings)
+}
+
+/// A native wrapper symlink or a script carrying our marker line. Only the
+/// start of the file is read, since install dirs also hold large binaries.
+fn is_generated(path: &Path) -> bool {
+    if let Ok(target) = fs::read_link(path) {
+        return target
+            .file_name()
+            .is_some_and(|name| name == "cargo-dev-install");
+    }
+    let mut head = Vec::new();
+    fs::File::open(path)
+        .and_then(|file| file.take(512).read_to_end(&mut head))
+        .is_ok_and(|_| install::has_marker(&String::from_utf8_lossy(&head)))
+}
+
+fn load(env: &crate::EnvSnapshot) -> Result<(Registry, Vec<PathBuf>), String> {
+    let registry_path = registry::registry_path(env)
+        .ok_or_else(|| "HOME is not set; cannot locate the wrapper registry".to_string())?;
+    let registry = Registry::load(&registry_path)?;
+    let install_dirs = install::install_dir(env).into_iter().collect();
+    Ok((registry, install_dirs))
+}
+
+/// Prints every finding and fails if there is any, for login-time checks.
+pub fn run(env: &crate::EnvSnapshot) -> Result<(), String> {
+    let (registry, install_dirs) = load(env)?;
+    let findings = verify(&registry, &install_dirs)?;
+    for finding in &findings {
+        println!("{finding}");
+    }
+    if !findings.is_empty() {
+        return Err(format!("{} wrapper(s) failed verification", findings.len()));
+    }
+    println!("{} wrapper(s) verified", registry.wrappers.len());
+    Ok(())
+}
+
+/// `verify`, plus checks that the install directory is on `PATH` and that
+/// every registered crate still exists.
+pub fn doctor(env: &crate::EnvSnapshot) -> Result<(), String> {
+    let (registry, install_dirs) = load(env)?;
+    let mut problems: Vec<String> = verify(&registry, &install_dirs)?
+        .iter()
+        .map(Finding::to_string)
+        .collect();
+
+    let cwd = std::env::current_dir().ok();
+    for dir in &install_dirs {
+        let status = install::is_on_path(
+            dir,
+            env.path.as_deref(),
+            env.home.as_deref(),
+            cwd.as_deref(),
+        );
+        if let Some(problem) = status.problem() {
+            problems.push(format!("{problem}: {}", dir.display()));
+        }
+    }
+    for entry in &registry.wrappers {
+        if !entry.manifest_path.is_file() {
+            problems.push(format!(
+                "crate missing: {} ({}); see `cargo dev-install relink`",
+                entry.name,
+                entry.manifest_path.display()
+            ));
+        }
+    }
+
+    for problem in &problems {
+        println!("{problem}");
+    }
+    if !problems.is_empty() {
+        return Err(format!("{} problem(s) found", problems.len()));
+    }
+    println!("no problems found");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::install::WrapperOptions;
+
+    fn entry(name: &str, wrapper_path: &Path, contents: &str) -> RegistryEntry {
+        RegistryEntry {
+            name: name.to_string(),
+            wrapper_path: wrapper_path.to_path_buf(),
+            crate_root: PathBuf::from("/repo"),
+            manifest_path: PathBuf::from("/repo/Cargo.toml"),
+            bin_name: name.to_string(),
+            package: None,
+            options: WrapperOptions::default(),
+            snapshot: None,
+            checkout: None,
+            backup: None,
+            hash: Some(install::content_hash(contents.as_bytes())),
+            aliases: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn reports_modified_missing_and_unexpected_wrappers() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let bin = dir.path();
+        let generated = install::render_wrapper(Path::new("/repo"), "demo", &Default::default());
+        for name in ["intact", "edited", "stray"] {
+            fs::write(bin.join(name), &generated).expect("write wrapper");
+        }
+        fs::write(bin.join("unrelated"), "#!/bin/sh\necho hi\n").expect("write script");
+        std::os::unix::fs::symlink("/usr/bin/cargo-dev-install", bin.join("native"))
+            .expect("symlink");
+
+        let registry = Registry {
+            wrappers: vec![
+                entry("intact", &bin.join("intact"), &generated),
+                entry("edited", &bin.join("edited"), "something else"),
+                entry("gone", &bin.join("gone"), &generated),
+            ],
+        };
+
+        let findings = verify(&registry, &[bin.to_path_buf()]).expect("verify");
+        let report: Vec<String> = findings
+            .iter()
+            .map(|finding| finding.to_string().replace(&bin.display().to_string(), ""))
+            .collect();
+        assert_eq!(
+            report,
+            [
+                "modified: /edited",
+                "missing: /gone",
+                "unexpected: /native",
+                "unexpected: /stray",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_missing_and_retargeted_aliases() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let bin = dir.path();
+        let generated = install::render_wrapper(Path::new("/repo"), "demo", &Default::default());
+        fs::write(bin.join("demo"), &generated).expect("write wrapper");
+        std::os::unix::fs::symlink("demo", bin.join("linked")).expect("symlink");
+        std::os::unix::fs::symlink("other", bin.join("moved")).expect("symlink");
+        fs::write(
+            bin.join("replaced"),
+            "#!/bin/sh
+",
+        )
+        .expect("write script");
+
+        let mut demo = entry("demo", &bin.join("demo"), &generated);
+        demo.aliases = ["linked", "moved", "replaced", "gone"]
+            .map(str::to_string)
+            .to_vec();
+        let registry = Registry {
+            wrappers: vec![demo],
+        };
+
+        let findings = verify(&registry, &[bin.to_path_buf()]).expect("verify");
+        let report: Vec<String> = findings
+            .iter()
+            .map(|finding| finding.to_string().replace(&bin.display().to_string(), ""))
+            .collect();
+        assert_eq!(
+            report,
+            ["modified: /moved", "modified: /replaced", "missing: /gone"]
+        );
+    }
+
+    #[test]
+    fn native_wrappers_are_hashed_by_link_target() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let wrapper_path = dir.path().join("demo");
+        let launcher = Path::new("/usr/bin/cargo-dev-install");
+        std::os::unix::fs::symlink(launcher, &wrapper_path).expect("symlink");
+
+        let mut entry = entry("demo", &wrapper_path, "");
+        entry.hash = Some(install::link_hash(launcher));
+        assert_eq!(check_entry(&entry), Ok(None));
+
+        entry.hash = Some(install::link_hash(Path::new(
+            "/elsewhere/cargo-dev-install",
+        )));
+        assert_eq!(check_entry(&entry), Ok(Some(Problem::Modified)));
+    }
+}
@@ -0,0 +1,419 @@
+use crate::launcher;
+use crate::registry::{self, Registry, RegistryEntry};
+use cargo_metadata::{DependencyKind, MetadataCommand};
+use std::collections::{HashMap, HashSet};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::{fs, io};
+
+/// Quiet period after the last change before rebuilding, so that a save
+/// touching several files triggers a single build.
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Source locations whose changes invalidate a wrapper's build.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WatchSet {
+    /// Watched recursively.
+    pub dirs: Vec<PathBuf>,
+    /// Individual files such as manifests, `Cargo.lock` and `build.rs`.
+    pub files: Vec<PathBuf>,
+}
+
+impl WatchSet {
+    pub fn matches(&self, path: &Path) -> bool {
+        self.files.iter().any(|file| file == path)
+            || self.dirs.iter().any(|dir| path.starts_with(dir))
+    }
+}
+
+/// Derives what to watch from cargo metadata: the directories holding each
+/// workspace target's `src_path`, every package manifest and `Cargo.lock`.
+/// Targets at a package root (like `build.rs`) are watched as single files so
+/// that the target directory is never watched. Path dependencies outside the
+/// workspace are followed, so editing a sibling crate rebuilds too.
+pub fn watch_set(manifest_path: &Path) -> Result<WatchSet, String> {
+    let mut set = WatchSet::default();
+    let mut seen = HashSet::new();
+    let mut pending = vec![(manifest_path.to_path_buf(), true)];
+    while let Some((manifest_path, workspace)) = pending.pop() {
+        // Without deps, metadata needs no registry index; path dependencies
+        // are read from each package's own dependency list instead.
+        let metadata = MetadataCommand::new()
+            .manifest_path(&manifest_path)
+            .no_deps()
+            .exec()
+            .map_err(|err| format!("failed to load cargo metadata: {err}"))?;
+        if workspace {
+            set.files
+                .push(metadata.workspace_root.as_std_path().join("Cargo.lock"));
+        }
+        let manifest_path = manifest_path.canonicalize().unwrap_or(manifest_path);
+        for package in &metadata.packages {
+            let package_manifest = package.manifest_path.as_std_path();
+            // Of a dependency's workspace only the dependency itself matters.
+            if !workspace && package_manifest != manifest_path {
+                continue;
+            }
+            if !seen.insert(package_manifest.to_path_buf()) {
+                continue;
+            }
+            set.files.push(package_manifest.to_path_buf());
+            let package_root = package_manifest.parent().unwrap_or(Path::new("/"));
+
+            for target in &package.targets {
+                let src_path = target.src_path.as_std_path();
+                match src_path.parent() {
+                    Some(dir) if dir != package_root => set.dirs.push(dir.to_path_buf()),
+                    _ => set.files.push(src_path.to_path_buf()),
+                }
+            }
+            for dependency in &package.dependencies {
+                if dependency.kind == DependencyKind::Development {
+                    continue;
+                }
+                if let Some(path) = &dependency.path {
+                    let manifest = path.as_std_path().join("Cargo.toml");
+                    if !seen.contains(&manifest) {
+                        pending.push((manifest, false));
+                    }
+                }
+            }
+        }
+    }
+
+    set.dirs.sort();
+    set.dirs.dedup();
+    let dirs = std::mem::take(&mut set.dirs);
+    for dir in dirs {
+        if !set.dirs.iter().any(|parent| dir.starts_with(parent)) {
+            set.dirs.push(dir);
+        }
+    }
+    set.files.sort();
+    set.files.dedup();
+    Ok(set)
+}
+
+pub fn run(env: &crate::EnvSnapshot) -> Result<(), String> {
+    let registry_path = registry::registry_path(env)
+        .ok_or_else(|| "HOME is not set; cannot locate the wrapper registry".to_string())?;
+    let registry = Registry::load(&registry_path)?;
+    if registry.wrappers.is_empty() {
+        return Err("no wrappers registered; install one with cargo dev-install".to_string());
+    }
+
+    let mut inotify = Inotify::new().map_err(|err| format!("failed to start inotify: {err}"))?;
+    // The registry is replaced by a rename, so its directory is watched.
+    if let Some(parent) = registry_path.parent() {
+        inotify.add(parent);
+    }
+    let mut watched = watch_registry(&registry, &mut inotify);
+    println!("watching {} wrapper(s)", watched.len());
+    for (entry, _) in &watched {
+        rebuild(entry);
+    }
+
+    loop {
+        let mut changed = inotify
+            .read_events(None)
+            .map_err(|err| format!("failed to read inotify events: {err}"))?;
+        loop {
+            let more = inotify
+                .read_events(Some(DEBOUNCE))
+                .map_err(|err| format!("failed to read inotify events: {err}"))?;
+            if more.is_empty() {
+                break;
+            }
+            changed.extend(more);
+        }
+
+        if changed.contains(&registry_path) {
+            match Registry::load(&registry_path) {
+                Ok(registry) => {
+                    watched = watch_registry(&registry, &mut inotify);
+                    println!("registry changed; watching {} wrapper(s)", watched.len());
+                }
+                Err(err) => eprintln!("warning: keeping the previous registry: {err}"),
+            }
+        }
+        for (entry, set) in &watched {
+            if changed.iter().any(|path| set.matches(path)) {
+                rebuild(entry);
+            }
+        }
+    }
+}
+
+/// Adds watches for the sources of every registered wrapper. Watches of
+/// wrappers that were uninstalled since stay, but no longer match anything.
+fn watch_registry(registry: &Registry, inotify: &mut Inotify) -> Vec<(RegistryEntry, WatchSet)> {
+    let mut watched = Vec::new();
+    for entry in &registry.wrappers {
+        match watch_set(&entry.manifest_path) {
+            Ok(set) => {
+                for dir in &set.dirs {
+                    inotify.add_recursive(dir);
+                }
+                for file in &set.files {
+                    if let Some(parent) = file.parent() {
+                        inotify.add(parent);
+                    }
+                }
+                watched.push((entry.clone(), set));
+            }
+            Err(err) => eprintln!("warning: not watching {}: {err}", entry.name),
+        }
+    }
+    watched
+}
+
+fn rebuild(entry: &RegistryEntry) {
+    match launcher::locked_build(entry) {
+        Ok(_) => println!("rebuilt {}", entry.name),
+        Err(err) => eprintln!("{err}"),
+    }
+}
+
+/// Minimal inotify wrapper reporting the paths that changed.
+struct Inotify {
+    fd: OwnedFd,
+    watches: HashMap<i32, (PathBuf, bool)>,
+}
+
+const WATCH_MASK: u32 = libc::IN_CLOSE_WRITE
+    | libc::IN_CREATE
+    | libc::IN_DELETE
+    | libc::IN_MOVED_FROM
+    | libc::IN_MOVED_TO;
+
+impl Inotify {
+    fn new() -> io::Result<Self> {
+        // SAFETY: plain syscall; the returned descriptor is owned below.
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC | libc::IN_NONBLOCK) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            // SAFETY: `fd` is a freshly created descriptor nobody else owns.
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+            watches: HashMap::new(),
+        })
+    }
+
+    fn add(&mut self, dir: &Path) {
+        self.add_watch(dir, false);
+    }
+
+    fn add_recursive(&mut self, dir: &Path) {
+        if !self.add_watch(dir, true) {
+            return;
+        }
+        if let Ok(entries) = fs::read_dir(dir) {
+            for entry in entries.flatten() {
+                if entry.file_type().is_ok_and(|kind| kind.is_dir()) {
+                    self.add_recursive(&entry.path());
+                }
+            }
+        }
+    }
+
+    fn add_watch(&mut self, dir: &Path, recursive: bool) -> bool {
+        use std::os::unix::ffi::OsStrExt;
+        let Ok(c_path) = std::ffi::CString::new(dir.as_os_str().as_bytes()) else {
+            return false;
+        };
+        // SAFETY: `c_path` is a valid NUL-terminated string.
+        let wd =
+            unsafe { libc::inotify_add_watch(self.fd.as_raw_fd(), c_path.as_ptr(), WATCH_MASK) };
+        if wd < 0 {
+            return false;
+        }
+        let watch = self.watches.entry(wd).or_insert((dir.to_path_buf(), false));
+        watch.1 |= recursive;
+        true
+    }
+
+    /// Waits up to `timeout` (forever if `None`) and returns changed paths.
+    /// Directories created inside recursive watches are watched as well.
+    fn read_events(&mut self, timeout: Option<Duration>) -> io::Result<Vec<PathBuf>> {
+        let mut pollfd = libc::pollfd {
+            fd: self.fd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout_ms = timeout.map_or(-1, |timeout| timeout.as_millis() as libc::c_int);
+        // SAFETY: `pollfd` points to one valid pollfd struct.
+        let ready = unsafe { libc::poll(&mut pollfd, 1, timeout_ms) };
+        if ready < 0 {
+            let err = io::Error::last_os_error();
+            return if err.kind() == io::ErrorKind::Interrupted {
+                Ok(Vec::new())
+            } else {
+                Err(err)
+            };
+        }
+        if ready == 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut buffer = [0u8; 8192];
+        // SAFETY: the buffer is valid for writes of its full length.
+        let len = unsafe {
+            libc::read(
+                self.fd.as_raw_fd(),
+                buffer.as_mut_ptr().cast(),
+                buffer.len(),
+            )
+        };
+        if len < 0 {
+            let err = io::Error::last_os_error();
+            return if err.kind() == io::ErrorKind::WouldBlock {
+                Ok(Vec::new())
+            } else {
+                Err(err)
+            };
+        }
+
+        let mut changed = Vec::new();
+        let mut new_dirs = Vec::new();
+        let header = std::mem::size_of::<libc::inotify_event>();
+        let mut offset = 0;
+        while offset + header <= len as usize {
+            // SAFETY: the kernel wrote a complete event header at `offset`.
+            let event: libc::inotify_event =
+                unsafe { std::ptr::read_unaligned(buffer[offset..].as_ptr().cast()) };
+            let name_bytes = &buffer[offset + header..offset + header + event.len as usize];
+            let name_len = name_bytes
+                .iter()
+                .position(|&b| b == 0)
+                .unwrap_or(name_bytes.len());
+            offset += header + event.len as usize;
+
+            let Some((dir, recursive)) = self.watches.get(&event.wd) else {
+                continue;
+            };
+            let path = {
+                use std::os::unix::ffi::OsStrExt;
+                dir.join(std::ffi::OsStr::from_bytes(&name_bytes[..name_len]))
+            };
+            if *recursive && event.mask & libc::IN_ISDIR != 0 && event.mask & libc::IN_CREATE != 0 {
+                new_dirs.push(path.clone());
+            }
+            changed.push(path);
+        }
+
+        for dir in new_dirs {
+            self.add_recursive(&dir);
+        }
+        Ok(changed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_file(path: &Path, contents: &str) {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).expect("create parent");
+        }
+        fs::write(path, contents).expect("write file");
+    }
+
+    #[test]
+    fn watch_set_covers_sources_manifest_and_lockfile() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        write_file(
+            &dir.path().join("Cargo.toml"),
+            "[package]\nname = \"demo\"\nversion = \"0.1.0\"\nedition = \"2021\"\nbuild = \"build.rs\"\n\n[[bin]]\nname = \"alpha\"\npath = \"src/main.rs\"\n\n[[bin]]\nname = \"beta\"\npath = \"src/bin/beta.rs\"\n",
+        );
+        write_file(&dir.path().join("src/main.rs"), "fn main() {}\n");
+        write_file(&dir.path().join("src/bin/beta.rs"), "fn main() {}\n");
+        write_file(&dir.path().join("build.rs"), "fn main() {}\n");
+
+        let set = watch_set(&dir.path().join("Cargo.toml")).expect("watch set");
+        let root = dir.path().canonicalize().expect("canonicalize");
+        assert_eq!(set.dirs, vec![root.join("src")]);
+        assert!(set.files.contains(&root.join("Cargo.toml")));
+        assert!(set.files.contains(&root.join("Cargo.lock")));
+        assert!(set.files.contains(&root.join("build.rs")));
+
+        assert!(set.matches(&root.join("src/bin/beta.rs")));
+        assert!(set.matches(&root.join("src/new_module.rs")));
+        assert!(!set.matches(&root.join("target/release/alpha")));
+        assert!(!set.matches(&root.join("README.md")));
+    }
+
+    #[test]
+    fn watch_set_follows_path_dependencies() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        write_file(
+            &dir.path().join("app/Cargo.toml"),
+            "[package]\nname = \"app\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[dependencies]\nlib = { path = \"../lib\" }\n\n[dev-dependencies]\nfixtures = { path = \"../fixtures\" }\n",
+        );
+        write_file(&dir.path().join("app/src/main.rs"), "fn main() {}\n");
+        for name in ["lib", "fixtures", "nested"] {
+            write_file(
+                &dir.path().join(format!("{name}/Cargo.toml")),
+                &format!("[package]\nname = \"{name}\"\nversion = \"0.1.0\"\nedition = \"2021\"\n"),
+            );
+            write_file(&dir.path().join(format!("{name}/src/lib.rs")), "\n");
+        }
+        // Dependencies of dependencies are followed as well.
+        fs::OpenOptions::new()
+            .append(true)
+            .open(dir.path().join("lib/Cargo.toml"))
+            .and_then(|mut manifest| {
+                use std::io::Write;
+                manifest.write_all(b"\n[dependencies]\nnested = { path = \"../nested\" }\n")
+            })
+            .expect("add nested dependency");
+
+        let set = watch_set(&dir.path().join("app/Cargo.toml")).expect("watch set");
+        let root = dir.path().canonicalize().expect("canonicalize");
+        assert_eq!(
+            set.dirs,
+            vec![
+                root.join("app/src"),
+                root.join("lib/src"),
+                root.join("nested/src")
+            ]
+        );
+        assert!(set.files.contains(&root.join("lib/Cargo.toml")));
+        assert!(set.files.contains(&root.join("app/Cargo.lock")));
+        assert!(!set.files.contains(&root.join("lib/Cargo.lock")));
+        assert!(!set.matches(&root.join("fixtures/src/lib.rs")));
+    }
+
+    #[test]
+    fn inotify_reports_changes_in_new_subdirectories() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let mut inotify = Inotify::new().expect("inotify");
+        inotify.add_recursive(dir.path());
+
+        fs::create_dir(dir.path().join("nested")).expect("create nested");
+        let changed = inotify
+            .read_events(Some(Duration::from_secs(5)))
+            .expect("read events");
+        assert!(changed.contains(&dir.path().join("nested")));
+
+        write_file(&dir.path().join("nested/lib.rs"), "// changed\n");
+        let changed = inotify
+            .read_events(Some(Duration::from_secs(5)))
+            .expect("read events");
+        assert!(changed.contains(&dir.path().join("nested/lib.rs")));
+    }
+
+    #[test]
+    fn inotify_times_out_without_changes() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let mut inotify = Inotify::new().expect("inotify");
+        inotify.add(dir.path());
+
+        let changed = inotify
+            .read_events(Some(Duration::from_millis(10)))
+            .expect("read events");
+        assert!(changed.is_empty());
+    }
+}
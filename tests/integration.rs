@@ -10,26 +10,50 @@ fn write_file(path: &Path, contents: &str) {
     fs::write(path, contents).expect("write file");
 }
 
-fn create_single_bin_crate(root: &Path) {
-    write_file(
-        &root.join("Cargo.toml"),
-        "[package]\nname = \"demo\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[[bin]]\nname = \"demo\"\npath = \"src/main.rs\"\n",
-    );
-    write_file(
-        &root.join("src/main.rs"),
-        "fn main() { println!(\"ok\"); }\n",
-    );
+const MAIN_OK: &str = "fn main() { println!(\"ok\"); }\n";
+
+const MAIN_ECHO_ARGS: &str = "fn main() {\n    println!(\"MARKER\");\n    for arg in std::env::args().skip(1) {\n        println!(\"arg:{arg}\");\n    }\n}\n";
+
+/// A crate with a single `demo` binary built from the given `src/main.rs`.
+struct DemoCrate<'a> {
+    main_rs: &'a str,
+    build_rs: Option<&'a str>,
+    manifest_extra: &'a str,
 }
 
-fn create_single_bin_crate_with_output(root: &Path) {
-    write_file(
-        &root.join("Cargo.toml"),
-        "[package]\nname = \"demo\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[[bin]]\nname = \"demo\"\npath = \"src/main.rs\"\n",
-    );
-    write_file(
-        &root.join("src/main.rs"),
-        "fn main() {\n    println!(\"MARKER\");\n    for arg in std::env::args().skip(1) {\n        println!(\"arg:{arg}\");\n    }\n}\n",
-    );
+impl<'a> DemoCrate<'a> {
+    fn new(main_rs: &'a str) -> Self {
+        Self {
+            main_rs,
+            build_rs: None,
+            manifest_extra: "",
+        }
+    }
+
+    fn build_rs(mut self, build_rs: &'a str) -> Self {
+        self.build_rs = Some(build_rs);
+        self
+    }
+
+    /// Extra manifest sections, inserted after `[package]`.
+    fn manifest_extra(mut self, manifest_extra: &'a str) -> Self {
+        self.manifest_extra = manifest_extra;
+        self
+    }
+
+    fn write(&self, root: &Path) {
+        write_file(
+            &root.join("Cargo.toml"),
+            &format!(
+                "[package]\nname = \"demo\"\nversion = \"0.1.0\"\nedition = \"2021\"\n{}\n[[bin]]\nname = \"demo\"\npath = \"src/main.rs\"\n",
+                self.manifest_extra
+            ),
+        );
+        write_file(&root.join("src/main.rs"), self.main_rs);
+        if let Some(build_rs) = self.build_rs {
+            write_file(&root.join("build.rs"), build_rs);
+        }
+    }
 }
 
 fn create_multi_bin_crate(root: &Path) {
@@ -41,6 +65,26 @@ fn create_multi_bin_crate(root: &Path) {
     write_file(&root.join("src/bin/beta.rs"), "fn main() {}\n");
 }
 
+/// POSIX shells available on this machine, as argv prefixes.
+fn posix_shells() -> Vec<Vec<String>> {
+    let mut shells = Vec::new();
+    for dir in ["/bin", "/usr/bin"] {
+        let dash = Path::new(dir).join("dash");
+        if dash.is_file() {
+            shells.push(vec![dash.display().to_string()]);
+            break;
+        }
+    }
+    for dir in ["/bin", "/usr/bin"] {
+        let busybox = Path::new(dir).join("busybox");
+        if busybox.is_file() {
+            shells.push(vec![busybox.display().to_string(), "sh".to_string()]);
+            break;
+        }
+    }
+    shells
+}
+
 fn run_plugin(
     cwd: &Path,
     home: &Path,
@@ -61,7 +105,7 @@ fn run_plugin(
 #[test]
 fn installs_wrapper_in_default_location() {
     let repo = tempfile::tempdir().expect("repo");
-    create_single_bin_crate(repo.path());
+    DemoCrate::new(MAIN_OK).write(repo.path());
 
     let home = tempfile::tempdir().expect("home");
     let path_var = "/usr/bin";
@@ -71,15 +115,14 @@ fn installs_wrapper_in_default_location() {
     let wrapper_path = home.path().join(".local/bin/demo");
     assert!(wrapper_path.is_file());
     let contents = fs::read_to_string(&wrapper_path).expect("read wrapper");
-    assert!(contents.contains(
-        "exec cargo run --quiet --release --manifest-path \"$REPO/Cargo.toml\" -- \"$@\""
-    ));
+    assert!(contents.contains("build --quiet --release --manifest-path \"$REPO/Cargo.toml\""));
+    assert!(contents.contains("run_program \"$EXE\" \"$@\""));
 }
 
 #[test]
 fn installs_wrapper_in_xdg_bin_home() {
     let repo = tempfile::tempdir().expect("repo");
-    create_single_bin_crate(repo.path());
+    DemoCrate::new(MAIN_OK).write(repo.path());
 
     let home = tempfile::tempdir().expect("home");
     let xdg = tempfile::tempdir().expect("xdg");
@@ -94,7 +137,7 @@ fn installs_wrapper_in_xdg_bin_home() {
 #[test]
 fn warns_when_install_dir_not_on_path() {
     let repo = tempfile::tempdir().expect("repo");
-    create_single_bin_crate(repo.path());
+    DemoCrate::new(MAIN_OK).write(repo.path());
 
     let home = tempfile::tempdir().expect("home");
     let path_var = "/usr/bin";
@@ -109,7 +152,7 @@ fn warns_when_install_dir_not_on_path() {
 #[test]
 fn does_not_warn_when_install_dir_on_path() {
     let repo = tempfile::tempdir().expect("repo");
-    create_single_bin_crate(repo.path());
+    DemoCrate::new(MAIN_OK).write(repo.path());
 
     let home = tempfile::tempdir().expect("home");
     let install_dir = home.path().join(".local/bin");
@@ -123,7 +166,7 @@ fn does_not_warn_when_install_dir_on_path() {
 #[test]
 fn refuses_overwrite_without_force() {
     let repo = tempfile::tempdir().expect("repo");
-    create_single_bin_crate(repo.path());
+    DemoCrate::new(MAIN_OK).write(repo.path());
 
     let home = tempfile::tempdir().expect("home");
     let install_dir = home.path().join(".local/bin");
@@ -137,9 +180,9 @@ fn refuses_overwrite_without_force() {
 }
 
 #[test]
-fn overwrites_with_force_flag() {
+fn force_replaces_foreign_files_only_with_force_foreign() {
     let repo = tempfile::tempdir().expect("repo");
-    create_single_bin_crate(repo.path());
+    DemoCrate::new(MAIN_OK).write(repo.path());
 
     let home = tempfile::tempdir().expect("home");
     let install_dir = home.path().join(".local/bin");
@@ -147,9 +190,374 @@ fn overwrites_with_force_flag() {
     let wrapper = install_dir.join("demo");
     fs::write(&wrapper, "echo old\n").expect("write wrapper");
 
-    run_plugin(repo.path(), home.path(), "/usr/bin", None, &["--force"]).success();
+    run_plugin(repo.path(), home.path(), "/usr/bin", None, &["--force"])
+        .failure()
+        .stderr(predicate::str::contains("use --force-foreign"));
+    assert_eq!(fs::read_to_string(&wrapper).expect("read"), "echo old\n");
+
+    run_plugin(
+        repo.path(),
+        home.path(),
+        "/usr/bin",
+        None,
+        &["--force-foreign"],
+    )
+    .success()
+    .stdout(predicate::str::contains("original saved to"));
     let contents = fs::read_to_string(&wrapper).expect("read wrapper");
     assert!(contents.contains("REPO=\""));
+
+    // Reinstalling over our own wrapper keeps the backup for uninstall.
+    run_plugin(
+        repo.path(),
+        home.path(),
+        "/usr/bin",
+        None,
+        &["--toolchain", "nightly", "--force"],
+    )
+    .success();
+
+    run_plugin(
+        repo.path(),
+        home.path(),
+        "/usr/bin",
+        None,
+        &["uninstall", "demo"],
+    )
+    .success()
+    .stdout(predicate::str::contains("restored"));
+    assert_eq!(fs::read_to_string(&wrapper).expect("read"), "echo old\n");
+    let registry = fs::read_to_string(
+        home.path()
+            .join(".local/share/cargo-dev-install/registry.json"),
+    )
+    .expect("read registry");
+    assert!(!registry.contains("\"demo\""));
+}
+
+#[test]
+fn refuses_world_writable_install_dir_without_override() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let repo = tempfile::tempdir().expect("repo");
+    DemoCrate::new(MAIN_OK).write(repo.path());
+
+    let home = tempfile::tempdir().expect("home");
+    let install_dir = home.path().join(".local/bin");
+    fs::create_dir_all(&install_dir).expect("create install dir");
+    fs::set_permissions(&install_dir, fs::Permissions::from_mode(0o777)).expect("chmod");
+
+    run_plugin(repo.path(), home.path(), "/usr/bin", None, &[])
+        .failure()
+        .stderr(predicate::str::contains("is world-writable"));
+    assert!(!install_dir.join("demo").exists());
+
+    run_plugin(
+        repo.path(),
+        home.path(),
+        "/usr/bin",
+        None,
+        &["--allow-unsafe-paths"],
+    )
+    .success()
+    .stderr(predicate::str::contains("warning: install directory"));
+    assert!(install_dir.join("demo").exists());
+}
+
+#[test]
+fn verify_reports_wrappers_changed_behind_our_back() {
+    let repo = tempfile::tempdir().expect("repo");
+    DemoCrate::new(MAIN_OK).write(repo.path());
+    let home = tempfile::tempdir().expect("home");
+    let install_dir = home.path().join(".local/bin");
+    let path_var = format!("{}:/usr/bin", install_dir.display());
+
+    run_plugin(repo.path(), home.path(), &path_var, None, &[]).success();
+    run_plugin(repo.path(), home.path(), &path_var, None, &["verify"])
+        .success()
+        .stdout(predicate::str::contains("1 wrapper(s) verified"));
+
+    let wrapper = install_dir.join("demo");
+    let contents = fs::read_to_string(&wrapper).expect("read wrapper");
+    fs::write(&wrapper, contents.replace("--release", "--offline")).expect("edit");
+    run_plugin(repo.path(), home.path(), &path_var, None, &["verify"])
+        .failure()
+        .stdout(predicate::str::contains(format!(
+            "modified: {}",
+            wrapper.display()
+        )));
+
+    fs::remove_file(&wrapper).expect("remove wrapper");
+    run_plugin(repo.path(), home.path(), &path_var, None, &["doctor"])
+        .failure()
+        .stdout(predicate::str::contains("missing: "));
+}
+
+#[test]
+fn local_install_stays_inside_the_workspace() {
+    let repo = tempfile::tempdir().expect("repo");
+    DemoCrate::new(MAIN_OK).write(repo.path());
+    let home = tempfile::tempdir().expect("home");
+
+    run_plugin(
+        repo.path(),
+        home.path(),
+        "/usr/bin",
+        None,
+        &["--local", "--envrc"],
+    )
+    .success()
+    .stdout(predicate::str::contains("added `PATH_add .bin` to .envrc"));
+
+    let bin_dir = repo.path().join(".bin");
+    assert!(bin_dir.join("demo").is_file());
+    assert_eq!(
+        fs::read_to_string(bin_dir.join(".gitignore")).expect("gitignore"),
+        "*\n"
+    );
+    assert_eq!(
+        fs::read_to_string(repo.path().join(".envrc")).expect("envrc"),
+        "PATH_add .bin\n"
+    );
+    assert!(!home.path().join(".local/bin").exists());
+
+    run_plugin(
+        repo.path(),
+        home.path(),
+        "/usr/bin",
+        None,
+        &["--local", "--envrc"],
+    )
+    .success()
+    .stdout(predicate::str::contains(".envrc").not());
+    assert_eq!(
+        fs::read_to_string(repo.path().join(".envrc")).expect("envrc"),
+        "PATH_add .bin\n"
+    );
+}
+
+fn assert_aliases_see_their_own_name(style: &str) {
+    let repo = tempfile::tempdir().expect("repo");
+    DemoCrate::new(
+        "fn main() {\n    println!(\"argv0:{}\", std::env::args().next().unwrap());\n}\n",
+    )
+    .write(repo.path());
+    let home = tempfile::tempdir().expect("home");
+    let install_dir = home.path().join(".local/bin");
+    let path_var = std::env::var("PATH").unwrap_or_default();
+
+    run_plugin(
+        repo.path(),
+        home.path(),
+        &path_var,
+        None,
+        &[
+            "--wrapper-style",
+            style,
+            "--alias",
+            "first",
+            "--alias",
+            "second",
+        ],
+    )
+    .success();
+
+    for name in ["demo", "first", "second"] {
+        let output = Command::new(install_dir.join(name))
+            .env("HOME", home.path())
+            .output()
+            .expect("run wrapper");
+        assert!(output.status.success(), "{name}: {output:?}");
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            format!("argv0:{name}\n")
+        );
+    }
+
+    // Dropping an alias on reinstall removes its link.
+    run_plugin(
+        repo.path(),
+        home.path(),
+        &path_var,
+        None,
+        &["--wrapper-style", style, "--alias", "first"],
+    )
+    .success();
+    assert!(install_dir.join("first").exists());
+    assert!(install_dir.join("second").symlink_metadata().is_err());
+
+    run_plugin(
+        repo.path(),
+        home.path(),
+        &path_var,
+        None,
+        &["uninstall", "demo"],
+    )
+    .success();
+    assert!(install_dir.join("first").symlink_metadata().is_err());
+}
+
+#[test]
+fn bash_wrapper_aliases_preserve_argv0() {
+    assert_aliases_see_their_own_name("bash");
+}
+
+#[test]
+fn native_wrapper_aliases_preserve_argv0() {
+    assert_aliases_see_their_own_name("native");
+}
+
+#[test]
+fn sh_wrapper_cannot_preserve_argv0() {
+    let repo = tempfile::tempdir().expect("repo");
+    DemoCrate::new(MAIN_OK).write(repo.path());
+    let home = tempfile::tempdir().expect("home");
+
+    run_plugin(
+        repo.path(),
+        home.path(),
+        "/usr/bin",
+        None,
+        &["--wrapper-style", "sh", "--preserve-argv0"],
+    )
+    .failure()
+    .stderr(predicate::str::contains("POSIX sh cannot set argv[0]"));
+}
+
+fn assert_exports_repository_context(style: &str, manifest_extra: &str, prefix: &str) {
+    let repo = tempfile::tempdir().expect("repo");
+    let crate_root = repo.path().join("tools/demo");
+    DemoCrate::new("fn main() {\n    let mut vars: Vec<_> = std::env::vars().filter(|(name, _)| name.starts_with(\"CTX_\") || name.starts_with(\"CARGO_DEV_INSTALL_\")).collect();\n    vars.sort();\n    for (name, value) in vars {\n        println!(\"{name}={value}\");\n    }\n}\n")
+        .manifest_extra(manifest_extra)
+        .write(&crate_root);
+    git(repo.path(), &["init", "--quiet"]);
+    git(repo.path(), &["add", "-A"]);
+    git(repo.path(), &["commit", "--quiet", "-m", "init"]);
+    let commit = Command::new("git")
+        .arg("-C")
+        .arg(repo.path())
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .expect("rev-parse");
+    let commit = String::from_utf8_lossy(&commit.stdout).trim().to_string();
+    let home = tempfile::tempdir().expect("home");
+    let path_var = std::env::var("PATH").unwrap_or_default();
+
+    run_plugin(
+        &crate_root,
+        home.path(),
+        &path_var,
+        None,
+        &["--wrapper-style", style],
+    )
+    .success();
+    let wrapper = home.path().join(".local/bin/demo");
+    let output = Command::new(&wrapper)
+        .env("HOME", home.path())
+        .output()
+        .expect("run wrapper");
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+
+    run_plugin(
+        &crate_root,
+        home.path(),
+        &path_var,
+        None,
+        &["--wrapper-style", style, "--export-context", "--force"],
+    )
+    .success();
+    let output = Command::new(&wrapper)
+        .env("HOME", home.path())
+        .output()
+        .expect("run wrapper");
+    assert!(output.status.success(), "{output:?}");
+    let repo_root = repo.path().canonicalize().expect("canonicalize");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!(
+            "{prefix}_GIT_COMMIT={commit}\n{prefix}_MANIFEST_DIR={}\n{prefix}_PROFILE=release\n{prefix}_REPO_ROOT={}\n{prefix}_WRAPPER=demo\n",
+            crate_root.display(),
+            repo_root.display()
+        )
+    );
+}
+
+#[test]
+fn bash_wrapper_exports_repository_context() {
+    assert_exports_repository_context("bash", "", "CARGO_DEV_INSTALL");
+}
+
+#[test]
+fn native_wrapper_exports_repository_context_with_project_prefix() {
+    assert_exports_repository_context(
+        "native",
+        "\n[package.metadata.dev-install]\nenv-prefix = \"CTX\"\n",
+        "CTX",
+    );
+}
+
+#[test]
+fn rustup_reads_repository_toolchain_file_at_run_time() {
+    // Needs the rustup proxy of the user running the tests.
+    let real_home = std::path::PathBuf::from(std::env::var_os("HOME").expect("HOME"));
+    let cargo_home = std::env::var_os("CARGO_HOME")
+        .map(std::path::PathBuf::from)
+        .unwrap_or_else(|| real_home.join(".cargo"));
+    let rustup_home = std::env::var_os("RUSTUP_HOME")
+        .map(std::path::PathBuf::from)
+        .unwrap_or_else(|| real_home.join(".rustup"));
+    if !cargo_home.join("bin/rustup").is_file() {
+        return;
+    }
+
+    let repo = tempfile::tempdir().expect("repo");
+    let crate_root = repo.path().join("tools/demo");
+    DemoCrate::new(MAIN_OK).write(&crate_root);
+    let toolchain_file = repo.path().join("rust-toolchain.toml");
+    write_file(
+        &toolchain_file,
+        "[toolchain]\nchannel = \"dev-install-missing\"\n",
+    );
+
+    let home = tempfile::tempdir().expect("home");
+    let mut cmd = assert_cmd::cargo::cargo_bin_cmd!("cargo-dev-install");
+    cmd.current_dir(&crate_root)
+        .env("HOME", home.path())
+        .env("PATH", "/usr/bin")
+        .env("CARGO_HOME", &cargo_home)
+        .env("RUSTUP_HOME", &rustup_home)
+        .assert()
+        .success();
+    let wrapper = home.path().join(".local/bin/demo");
+    let contents = fs::read_to_string(&wrapper).expect("read wrapper");
+    assert!(!contents.contains("RUSTUP_TOOLCHAIN=\""));
+
+    let run = || {
+        Command::new(&wrapper)
+            .env("HOME", home.path())
+            .env("RUSTUP_AUTO_INSTALL", "0")
+            // An inherited pin does not override the repository's file.
+            .env("RUSTUP_TOOLCHAIN", "stable")
+            .env_remove("CARGO_TARGET_DIR")
+            .output()
+            .expect("run wrapper")
+    };
+    // The file above the crate root is found from the wrapper's build dir.
+    let output = run();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success(), "{stderr}");
+    assert!(stderr.contains("dev-install-missing"), "{stderr}");
+
+    // Changing the file takes effect without reinstalling.
+    fs::remove_file(&toolchain_file).expect("remove toolchain file");
+    let output = run();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "ok\n");
 }
 
 #[test]
@@ -174,7 +582,7 @@ fn supports_multi_bin_with_bin_flag() {
 #[test]
 fn executes_installed_wrapper() {
     let repo = tempfile::tempdir().expect("repo");
-    create_single_bin_crate_with_output(repo.path());
+    DemoCrate::new(MAIN_ECHO_ARGS).write(repo.path());
 
     let home = tempfile::tempdir().expect("home");
     let path_var = "/usr/bin";
@@ -202,3 +610,1134 @@ fn executes_installed_wrapper() {
     assert!(stdout.contains("MARKER"));
     assert!(stdout.contains("arg:hello"));
 }
+
+#[test]
+fn wrapper_builds_with_repo_cargo_config_outside_repo() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let repo = tempfile::tempdir().expect("repo");
+    DemoCrate::new(
+        "fn main() {\n    println!(\"config:{}\", option_env!(\"DEMO_CONFIG\").unwrap_or(\"unset\"));\n    println!(\"runtime:{}\", std::env::var(\"DEMO_CONFIG\").unwrap_or_else(|_| \"unset\".to_string()));\n    println!(\"cwd:{}\", std::env::current_dir().unwrap().display());\n}\n",
+    )
+    .write(repo.path());
+    write_file(
+        &repo.path().join(".cargo/config.toml"),
+        "[env]\nDEMO_CONFIG = \"from-repo-config\"\n\n[target.'cfg(all())']\nrunner = \"tools/runner.sh --marker\"\n",
+    );
+    let runner = repo.path().join("tools/runner.sh");
+    write_file(
+        &runner,
+        "#!/bin/sh\necho \"runner:$1\"\nshift\nexec \"$@\"\n",
+    );
+    fs::set_permissions(&runner, fs::Permissions::from_mode(0o755)).expect("chmod runner");
+
+    let elsewhere = tempfile::tempdir().expect("elsewhere");
+    for style in ["bash", "native"] {
+        let home = tempfile::tempdir().expect("home");
+        run_plugin(
+            repo.path(),
+            home.path(),
+            "/usr/bin",
+            None,
+            &["--wrapper-style", style],
+        )
+        .success();
+
+        // Like `cargo run`, the program gets the repository's `[env]` and is
+        // started through its runner, but in the caller's working directory.
+        let wrapper = home.path().join(".local/bin/demo");
+        let output = Command::new(&wrapper)
+            .current_dir(elsewhere.path())
+            .env("HOME", home.path())
+            .env("CARGO_TARGET_DIR", repo.path().join("target"))
+            .output()
+            .expect("run wrapper");
+        assert!(
+            output.status.success(),
+            "{style}: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.contains("runner:--marker"), "{style}: {stdout}");
+        assert!(
+            stdout.contains("config:from-repo-config"),
+            "{style}: {stdout}"
+        );
+        assert!(
+            stdout.contains("runtime:from-repo-config"),
+            "{style}: {stdout}"
+        );
+        assert!(
+            stdout.contains(&format!("cwd:{}", elsewhere.path().display())),
+            "{style}: {stdout}"
+        );
+    }
+}
+
+#[test]
+fn wrapper_runs_without_cargo_on_path() {
+    let repo = tempfile::tempdir().expect("repo");
+    DemoCrate::new(MAIN_ECHO_ARGS).write(repo.path());
+
+    let real_home = std::env::var_os("HOME").expect("HOME");
+    let cargo_home = std::env::var_os("CARGO_HOME")
+        .map(std::path::PathBuf::from)
+        .unwrap_or_else(|| Path::new(&real_home).join(".cargo"));
+    let rustup_home = std::env::var_os("RUSTUP_HOME")
+        .map(std::path::PathBuf::from)
+        .unwrap_or_else(|| Path::new(&real_home).join(".rustup"));
+
+    let home = tempfile::tempdir().expect("home");
+    let mut cmd = assert_cmd::cargo::cargo_bin_cmd!("cargo-dev-install");
+    cmd.current_dir(repo.path())
+        .env("HOME", home.path())
+        .env("PATH", "/usr/bin")
+        .env("CARGO_HOME", &cargo_home)
+        .env("RUSTUP_HOME", &rustup_home)
+        .assert()
+        .success();
+
+    let wrapper = home.path().join(".local/bin/demo");
+    let output = Command::new(&wrapper)
+        .env_clear()
+        .env("PATH", "/usr/bin:/bin")
+        .env("CARGO_TARGET_DIR", repo.path().join("target"))
+        .current_dir(repo.path())
+        .output()
+        .expect("run wrapper");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(String::from_utf8_lossy(&output.stdout).contains("MARKER"));
+}
+
+#[test]
+fn sanitized_wrapper_ignores_inherited_cargo_env() {
+    let repo = tempfile::tempdir().expect("repo");
+    DemoCrate::new(
+        "fn main() {\n    println!(\"leaked_cfg:{}\", cfg!(leaked));\n    println!(\"target_dir:{}\", std::env::var(\"CARGO_TARGET_DIR\").unwrap_or_default());\n}\n",
+    )
+    .write(repo.path());
+
+    let home = tempfile::tempdir().expect("home");
+    run_plugin(
+        repo.path(),
+        home.path(),
+        "/usr/bin",
+        None,
+        &["--sanitize-env"],
+    )
+    .success();
+
+    let leaked_target = home.path().join("leaked-target");
+    let wrapper = home.path().join(".local/bin/demo");
+    let output = Command::new(&wrapper)
+        .current_dir(repo.path())
+        .env("CARGO_TARGET_DIR", &leaked_target)
+        .env("RUSTFLAGS", "--cfg leaked")
+        .output()
+        .expect("run wrapper");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("leaked_cfg:false"));
+    assert!(stdout.contains(&format!("target_dir:{}", leaked_target.display())));
+    assert!(!leaked_target.exists());
+    assert!(repo.path().join("target/release/demo").is_file());
+}
+
+#[test]
+fn isolated_target_dir_builds_into_cache() {
+    let repo = tempfile::tempdir().expect("repo");
+    DemoCrate::new(MAIN_ECHO_ARGS).write(repo.path());
+
+    let home = tempfile::tempdir().expect("home");
+    let cache = tempfile::tempdir().expect("cache");
+    let mut cmd = assert_cmd::cargo::cargo_bin_cmd!("cargo-dev-install");
+    cmd.current_dir(repo.path())
+        .env("HOME", home.path())
+        .env("PATH", "/usr/bin")
+        .env("XDG_CACHE_HOME", cache.path())
+        .arg("--isolated-target-dir")
+        .assert()
+        .success();
+
+    let wrapper = home.path().join(".local/bin/demo");
+    let output = Command::new(&wrapper)
+        .current_dir(repo.path())
+        .env_remove("CARGO_TARGET_DIR")
+        .output()
+        .expect("run wrapper");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(String::from_utf8_lossy(&output.stdout).contains("MARKER"));
+    assert!(
+        cache
+            .path()
+            .join("cargo-dev-install/targets")
+            .join(cargo_dev_install::install::cache_key(&wrapper))
+            .join("release/demo")
+            .is_file()
+    );
+    assert!(!repo.path().join("target").exists());
+}
+
+#[test]
+fn native_wrapper_launches_through_registry() {
+    let repo = tempfile::tempdir().expect("repo");
+    DemoCrate::new(MAIN_ECHO_ARGS).write(repo.path());
+
+    let home = tempfile::tempdir().expect("home");
+    run_plugin(
+        repo.path(),
+        home.path(),
+        "/usr/bin",
+        None,
+        &["--wrapper-style", "native"],
+    )
+    .success();
+
+    let wrapper = home.path().join(".local/bin/demo");
+    let link = fs::read_link(&wrapper).expect("wrapper is a symlink");
+    assert_eq!(
+        link.file_name().and_then(|name| name.to_str()),
+        Some("cargo-dev-install")
+    );
+    let registry = home
+        .path()
+        .join(".local/share/cargo-dev-install/registry.json");
+    let registry = fs::read_to_string(registry).expect("read registry");
+    assert!(registry.contains("\"style\": \"native\""));
+
+    let elsewhere = tempfile::tempdir().expect("elsewhere");
+    let output = Command::new(&wrapper)
+        .current_dir(elsewhere.path())
+        .env("HOME", home.path())
+        .env("CARGO_TARGET_DIR", repo.path().join("target"))
+        .args(["hello", "two words"])
+        .output()
+        .expect("run wrapper");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("MARKER"));
+    assert!(stdout.contains("arg:hello"));
+    assert!(stdout.contains("arg:two words"));
+}
+
+#[test]
+fn unregistered_name_runs_the_cli() {
+    let home = tempfile::tempdir().expect("home");
+    let bin_dir = home.path().join("bin");
+    fs::create_dir_all(&bin_dir).expect("create bin dir");
+    let renamed = bin_dir.join("cargo-dev-install-old");
+    std::os::unix::fs::symlink(assert_cmd::cargo::cargo_bin!("cargo-dev-install"), &renamed)
+        .expect("create symlink");
+
+    let output = Command::new(&renamed)
+        .arg("--help")
+        .env("HOME", home.path())
+        .output()
+        .expect("run renamed binary");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Usage: cargo-dev-install-old"), "{stderr}");
+    assert!(!stderr.contains("no native wrapper registered"), "{stderr}");
+}
+
+#[test]
+fn sh_style_wrapper_runs_under_posix_shells() {
+    let repo = tempfile::tempdir().expect("repo");
+    DemoCrate::new(MAIN_ECHO_ARGS).write(repo.path());
+
+    let home = tempfile::tempdir().expect("home");
+    run_plugin(
+        repo.path(),
+        home.path(),
+        "/usr/bin",
+        None,
+        &["--wrapper-style", "sh"],
+    )
+    .success();
+
+    let wrapper = home.path().join(".local/bin/demo");
+    let contents = fs::read_to_string(&wrapper).expect("read wrapper");
+    assert!(contents.starts_with("#!/bin/sh\n"));
+
+    let mut shells = posix_shells();
+    shells.push(Vec::new());
+    for shell in shells {
+        let mut cmd = match shell.split_first() {
+            Some((program, rest)) => {
+                let mut cmd = Command::new(program);
+                cmd.args(rest).arg(&wrapper);
+                cmd
+            }
+            None => Command::new(&wrapper),
+        };
+        let output = cmd
+            .current_dir(repo.path())
+            .env("CARGO_TARGET_DIR", repo.path().join("target"))
+            .args(["hello", "two words"])
+            .output()
+            .expect("run wrapper");
+        assert!(
+            output.status.success(),
+            "{shell:?}: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.contains("MARKER"), "{shell:?}");
+        assert!(stdout.contains("arg:hello"), "{shell:?}");
+        assert!(stdout.contains("arg:two words"), "{shell:?}");
+    }
+}
+
+#[test]
+fn sh_style_wrapper_propagates_build_failure() {
+    let repo = tempfile::tempdir().expect("repo");
+    DemoCrate::new(MAIN_OK).write(repo.path());
+
+    let home = tempfile::tempdir().expect("home");
+    run_plugin(
+        repo.path(),
+        home.path(),
+        "/usr/bin",
+        None,
+        &["--wrapper-style", "sh"],
+    )
+    .success();
+    write_file(&repo.path().join("src/main.rs"), "fn main() { broken }\n");
+
+    let wrapper = home.path().join(".local/bin/demo");
+    for shell in posix_shells() {
+        let (program, rest) = shell.split_first().expect("shell program");
+        let output = Command::new(program)
+            .args(rest)
+            .arg(&wrapper)
+            .current_dir(repo.path())
+            .env("CARGO_TARGET_DIR", repo.path().join("target"))
+            .output()
+            .expect("run wrapper");
+        assert!(!output.status.success(), "{shell:?}");
+        assert!(
+            String::from_utf8_lossy(&output.stdout).is_empty(),
+            "{shell:?}"
+        );
+    }
+}
+
+fn assert_parallel_invocations_share_one_build(style: &str) {
+    let repo = tempfile::tempdir().expect("repo");
+    DemoCrate::new(
+        "fn main() {\n    println!(\"MARKER\");\n    println!(\"fd9:{}\", std::path::Path::new(\"/proc/self/fd/9\").exists());\n}\n",
+    )
+    .write(repo.path());
+
+    let home = tempfile::tempdir().expect("home");
+    run_plugin(
+        repo.path(),
+        home.path(),
+        "/usr/bin",
+        None,
+        &["--wrapper-style", style],
+    )
+    .success();
+
+    let wrapper = home.path().join(".local/bin/demo");
+    let children: Vec<_> = (0..4)
+        .map(|_| {
+            Command::new(&wrapper)
+                .current_dir(repo.path())
+                .env("HOME", home.path())
+                .env("CARGO_TARGET_DIR", repo.path().join("target"))
+                .stdout(std::process::Stdio::piped())
+                .stderr(std::process::Stdio::piped())
+                .spawn()
+                .expect("spawn wrapper")
+        })
+        .collect();
+
+    let mut notices = 0;
+    for child in children {
+        let output = child.wait_with_output().expect("wait wrapper");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "{style}: {stderr}");
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.contains("MARKER"), "{style}");
+        assert!(
+            stdout.contains("fd9:false"),
+            "{style}: lock leaked to program"
+        );
+        notices += stderr.matches("rebuilding demo").count();
+    }
+    assert!(notices <= 1, "{style}: {notices} rebuild notices");
+    assert!(
+        home.path()
+            .join(".cache/cargo-dev-install/locks")
+            .join(format!(
+                "{}.lock",
+                cargo_dev_install::install::cache_key(&wrapper)
+            ))
+            .is_file()
+    );
+}
+
+#[test]
+fn parallel_bash_wrappers_serialize_rebuild() {
+    assert_parallel_invocations_share_one_build("bash");
+}
+
+#[test]
+fn parallel_native_wrappers_serialize_rebuild() {
+    assert_parallel_invocations_share_one_build("native");
+}
+
+fn assert_build_script_children_do_not_hold_the_lock(style: &str) {
+    use std::time::{Duration, Instant};
+
+    let repo = tempfile::tempdir().expect("repo");
+    DemoCrate::new(MAIN_OK)
+        .build_rs(
+            "use std::process::{Command, Stdio};\n\nfn main() {\n    Command::new(\"sleep\").arg(\"30\").stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::null()).spawn().expect(\"spawn sleep\");\n}\n",
+        )
+        .write(repo.path());
+
+    let home = tempfile::tempdir().expect("home");
+    run_plugin(
+        repo.path(),
+        home.path(),
+        "/usr/bin",
+        None,
+        &["--wrapper-style", style],
+    )
+    .success();
+
+    let wrapper = home.path().join(".local/bin/demo");
+    let output = Command::new(&wrapper)
+        .env("HOME", home.path())
+        .output()
+        .expect("run wrapper");
+    assert!(output.status.success(), "{style}: {output:?}");
+
+    // The `sleep` left behind by build.rs is still running.
+    let mut second = Command::new(&wrapper)
+        .env("HOME", home.path())
+        .stdout(std::process::Stdio::null())
+        .spawn()
+        .expect("spawn wrapper");
+    let started = Instant::now();
+    while second.try_wait().expect("wait wrapper").is_none() {
+        if started.elapsed() > Duration::from_secs(20) {
+            second.kill().expect("kill wrapper");
+            panic!("{style}: wrapper waited for a build script's child to exit");
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
+
+#[test]
+fn bash_build_script_children_do_not_hold_the_lock() {
+    assert_build_script_children_do_not_hold_the_lock("bash");
+}
+
+#[test]
+fn native_build_script_children_do_not_hold_the_lock() {
+    assert_build_script_children_do_not_hold_the_lock("native");
+}
+
+fn assert_quiet_build_output(style: &str) {
+    let repo = tempfile::tempdir().expect("repo");
+    DemoCrate::new("fn main() {\n    let unused_marker = 1;\n    println!(\"ok\");\n}\n")
+        .write(repo.path());
+
+    let home = tempfile::tempdir().expect("home");
+    run_plugin(
+        repo.path(),
+        home.path(),
+        "/usr/bin",
+        None,
+        &["--wrapper-style", style, "--build-output", "quiet"],
+    )
+    .success();
+
+    let wrapper = home.path().join(".local/bin/demo");
+    let run = |override_mode: Option<&str>| {
+        let mut cmd = Command::new(&wrapper);
+        cmd.current_dir(repo.path())
+            .env("HOME", home.path())
+            .env("CARGO_TARGET_DIR", repo.path().join("target"));
+        if let Some(mode) = override_mode {
+            cmd.env("CARGO_DEV_INSTALL_BUILD_OUTPUT", mode);
+        }
+        cmd.output().expect("run wrapper")
+    };
+
+    let output = run(None);
+    assert!(output.status.success(), "{style}");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "ok\n");
+    assert!(
+        output.stderr.is_empty(),
+        "{style}: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    write_file(
+        &repo.path().join("src/main.rs"),
+        "fn main() {\n    let unused_marker = 2;\n    println!(\"ok\");\n}\n",
+    );
+    let output = run(Some("cargo"));
+    assert!(output.status.success(), "{style}");
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("unused_marker"),
+        "{style}"
+    );
+
+    write_file(
+        &repo.path().join("src/main.rs"),
+        "fn main() { broken_call(); }\n",
+    );
+    let output = run(None);
+    assert!(!output.status.success(), "{style}");
+    assert!(output.stdout.is_empty(), "{style}");
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("broken_call"),
+        "{style}"
+    );
+}
+
+#[test]
+fn quiet_build_output_shows_diagnostics_only_on_failure() {
+    assert_quiet_build_output("bash");
+}
+
+#[test]
+fn native_quiet_build_output_shows_diagnostics_only_on_failure() {
+    assert_quiet_build_output("native");
+}
+
+fn assert_falls_back_to_last_good(style: &str) {
+    let repo = tempfile::tempdir().expect("repo");
+    DemoCrate::new("fn main() { println!(\"good build\"); }\n").write(repo.path());
+
+    let home = tempfile::tempdir().expect("home");
+    run_plugin(
+        repo.path(),
+        home.path(),
+        "/usr/bin",
+        None,
+        &["--wrapper-style", style, "--fallback-last-good"],
+    )
+    .success();
+
+    let wrapper = home.path().join(".local/bin/demo");
+    let run = || {
+        Command::new(&wrapper)
+            .current_dir(repo.path())
+            .env("HOME", home.path())
+            .env("CARGO_TARGET_DIR", repo.path().join("target"))
+            .output()
+            .expect("run wrapper")
+    };
+
+    let output = run();
+    assert!(output.status.success(), "{style}");
+    assert!(
+        home.path()
+            .join(".cache/cargo-dev-install/last-good")
+            .join(cargo_dev_install::install::cache_key(&wrapper))
+            .is_file()
+    );
+
+    write_file(
+        &repo.path().join("src/main.rs"),
+        "fn main() { broken_call(); }\n",
+    );
+    let output = run();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{style}: {stderr}");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "good build\n");
+    assert!(
+        stderr.contains("running last good build (commit "),
+        "{style}: {stderr}"
+    );
+
+    run_plugin(
+        repo.path(),
+        home.path(),
+        "/usr/bin",
+        None,
+        &[
+            "--wrapper-style",
+            style,
+            "--fallback-last-good",
+            "--strict",
+            "--force",
+        ],
+    )
+    .success();
+    let output = run();
+    // cargo exits with 101 when compilation fails.
+    assert_eq!(output.status.code(), Some(101), "{style}");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "good build\n");
+
+    // The same wrapper installed from another, broken crate must not run the
+    // first crate's last good build.
+    let other = tempfile::tempdir().expect("other repo");
+    DemoCrate::new("fn main() { broken_call(); }\n").write(other.path());
+    run_plugin(
+        other.path(),
+        home.path(),
+        "/usr/bin",
+        None,
+        &["--wrapper-style", style, "--fallback-last-good", "--force"],
+    )
+    .success();
+    let output = run();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success(), "{style}: {stderr}");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "", "{style}");
+    assert!(
+        stderr.contains("which was built from another crate"),
+        "{style}: {stderr}"
+    );
+}
+
+#[test]
+fn falls_back_to_last_good_build() {
+    assert_falls_back_to_last_good("bash");
+}
+
+#[test]
+fn native_falls_back_to_last_good_build() {
+    assert_falls_back_to_last_good("native");
+}
+
+#[test]
+fn build_flag_refuses_to_install_broken_crate() {
+    let repo = tempfile::tempdir().expect("repo");
+    DemoCrate::new("fn main() { broken_call(); }\n").write(repo.path());
+
+    let home = tempfile::tempdir().expect("home");
+    let mut cmd = assert_cmd::cargo::cargo_bin_cmd!("cargo-dev-install");
+    cmd.current_dir(repo.path())
+        .env("HOME", home.path())
+        .env("PATH", "/usr/bin")
+        .env("CARGO_TARGET_DIR", repo.path().join("target"))
+        .arg("--build")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("broken_call"))
+        .stderr(predicate::str::contains("wrapper not installed"));
+
+    assert!(!home.path().join(".local/bin/demo").exists());
+}
+
+#[test]
+fn build_and_verify_args_run_installed_wrapper() {
+    let repo = tempfile::tempdir().expect("repo");
+    DemoCrate::new("fn main() {\n    if std::env::args().nth(1).as_deref() != Some(\"--version\") {\n        std::process::exit(3);\n    }\n}\n").write(repo.path());
+
+    let home = tempfile::tempdir().expect("home");
+    let mut cmd = assert_cmd::cargo::cargo_bin_cmd!("cargo-dev-install");
+    cmd.current_dir(repo.path())
+        .env("HOME", home.path())
+        .env("PATH", "/usr/bin")
+        .env("CARGO_TARGET_DIR", repo.path().join("target"))
+        .args(["--build", "--verify-args", "--version"])
+        .assert()
+        .success();
+    assert!(repo.path().join("target/release/demo").is_file());
+
+    let mut cmd = assert_cmd::cargo::cargo_bin_cmd!("cargo-dev-install");
+    cmd.current_dir(repo.path())
+        .env("HOME", home.path())
+        .env("PATH", "/usr/bin")
+        .env("CARGO_TARGET_DIR", repo.path().join("target"))
+        .args(["--force", "--verify-args", "--help"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("verification failed"));
+}
+
+#[test]
+fn watch_rebuilds_registered_wrapper_on_source_change() {
+    use std::io::{BufRead, BufReader};
+    use std::process::Stdio;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    let repo = tempfile::tempdir().expect("repo");
+    DemoCrate::new("fn main() { println!(\"before\"); }\n").write(repo.path());
+
+    let home = tempfile::tempdir().expect("home");
+    run_plugin(repo.path(), home.path(), "/usr/bin", None, &[]).success();
+
+    let mut watcher = Command::new(env!("CARGO_BIN_EXE_cargo-dev-install"))
+        .arg("watch")
+        .env("HOME", home.path())
+        .env("CARGO_TARGET_DIR", repo.path().join("target"))
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("spawn watch");
+    let stdout = watcher.stdout.take().expect("stdout");
+    let (lines_tx, lines) = mpsc::channel();
+    std::thread::spawn(move || {
+        for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+            if lines_tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    let wait_for_rebuild = || loop {
+        match lines.recv_timeout(Duration::from_secs(120)) {
+            Ok(line) if line == "rebuilt demo" => break true,
+            Ok(_) => {}
+            Err(_) => break false,
+        }
+    };
+
+    let initial = wait_for_rebuild();
+    write_file(
+        &repo.path().join("src/main.rs"),
+        "fn main() { println!(\"after\"); }\n",
+    );
+    let rebuilt = initial && wait_for_rebuild();
+    let _ = watcher.kill();
+    let _ = watcher.wait();
+    assert!(initial, "initial build did not finish");
+    assert!(rebuilt, "watch did not rebuild after the source change");
+
+    let exe = repo.path().join("target/release/demo");
+    let output = Command::new(exe).output().expect("run rebuilt binary");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "after\n");
+}
+
+fn git(dir: &Path, args: &[&str]) {
+    let status = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+        .args(args)
+        .stdout(std::process::Stdio::null())
+        .status()
+        .expect("run git");
+    assert!(status.success(), "git {args:?}");
+}
+
+#[test]
+fn snapshot_wrapper_ignores_edits_until_update() {
+    let repo = tempfile::tempdir().expect("repo");
+    DemoCrate::new("fn main() { println!(\"v1\"); }\n").write(repo.path());
+    git(repo.path(), &["init", "--quiet"]);
+    git(repo.path(), &["add", "-A"]);
+    git(repo.path(), &["commit", "--quiet", "-m", "v1"]);
+
+    let home = tempfile::tempdir().expect("home");
+    run_plugin(repo.path(), home.path(), "/usr/bin", None, &["--snapshot"]).success();
+
+    let wrapper = home.path().join(".local/bin/demo");
+    let snapshot_root = home
+        .path()
+        .join(".local/share/cargo-dev-install/snapshots")
+        .join(cargo_dev_install::install::cache_key(&wrapper));
+    let contents = fs::read_to_string(&wrapper).expect("read wrapper");
+    assert!(contents.contains(&format!("REPO=\"{}\"", snapshot_root.display())));
+
+    let run = || {
+        let output = Command::new(&wrapper)
+            .env("HOME", home.path())
+            .env_remove("CARGO_TARGET_DIR")
+            .output()
+            .expect("run wrapper");
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    write_file(
+        &repo.path().join("src/main.rs"),
+        "fn main() { println!(\"v2\"); }\n",
+    );
+    assert_eq!(run(), "v1\n");
+
+    git(repo.path(), &["commit", "--quiet", "-am", "v2"]);
+    assert_eq!(run(), "v1\n");
+
+    run_plugin(repo.path(), home.path(), "/usr/bin", None, &["update"])
+        .success()
+        .stdout(predicate::str::contains("demo: updated"));
+    assert_eq!(run(), "v2\n");
+
+    run_plugin(
+        repo.path(),
+        home.path(),
+        "/usr/bin",
+        None,
+        &["update", "demo", "--rev", "HEAD~1"],
+    )
+    .success();
+    assert_eq!(run(), "v1\n");
+
+    run_plugin(
+        repo.path(),
+        home.path(),
+        "/usr/bin",
+        None,
+        &["update", "other"],
+    )
+    .failure()
+    .stderr(predicate::str::contains(
+        "no snapshot or --git wrapper named 'other'",
+    ));
+}
+
+#[test]
+fn named_snapshot_wrapper_sits_next_to_a_live_one() {
+    let repo = tempfile::tempdir().expect("repo");
+    DemoCrate::new("fn main() { println!(\"v1\"); }\n").write(repo.path());
+    git(repo.path(), &["init", "--quiet"]);
+    git(repo.path(), &["add", "-A"]);
+    git(repo.path(), &["commit", "--quiet", "-m", "v1"]);
+
+    let home = tempfile::tempdir().expect("home");
+    run_plugin(repo.path(), home.path(), "/usr/bin", None, &["--snapshot"]).success();
+    run_plugin(
+        repo.path(),
+        home.path(),
+        "/usr/bin",
+        None,
+        &["--name", "demo-dev"],
+    )
+    .success()
+    .stdout(predicate::str::contains("installed"));
+
+    write_file(
+        &repo.path().join("src/main.rs"),
+        "fn main() { println!(\"v2\"); }\n",
+    );
+    let run = |name: &str| {
+        let output = Command::new(home.path().join(".local/bin").join(name))
+            .env("HOME", home.path())
+            .env_remove("CARGO_TARGET_DIR")
+            .output()
+            .expect("run wrapper");
+        assert!(output.status.success(), "{output:?}");
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+    assert_eq!(run("demo"), "v1\n");
+    assert_eq!(run("demo-dev"), "v2\n");
+
+    // A refused install does not check out a worktree first.
+    write_file(&home.path().join(".local/bin/taken"), "#!/bin/sh\n");
+    run_plugin(
+        repo.path(),
+        home.path(),
+        "/usr/bin",
+        None,
+        &["--snapshot", "--name", "taken"],
+    )
+    .failure()
+    .stderr(predicate::str::contains("--force-foreign"));
+    let snapshots = fs::read_dir(home.path().join(".local/share/cargo-dev-install/snapshots"))
+        .expect("snapshots dir")
+        .count();
+    assert_eq!(snapshots, 1);
+
+    // Uninstalling the snapshot wrapper removes its worktree as well.
+    run_plugin(
+        repo.path(),
+        home.path(),
+        "/usr/bin",
+        None,
+        &["uninstall", "demo"],
+    )
+    .success();
+    let snapshots = fs::read_dir(home.path().join(".local/share/cargo-dev-install/snapshots"))
+        .expect("snapshots dir")
+        .count();
+    assert_eq!(snapshots, 0);
+    let worktrees = Command::new("git")
+        .args(["-C", &repo.path().display().to_string(), "worktree", "list"])
+        .output()
+        .expect("git worktree list");
+    assert_eq!(
+        String::from_utf8_lossy(&worktrees.stdout).lines().count(),
+        1
+    );
+}
+
+#[test]
+fn git_install_builds_from_managed_checkout_and_updates() {
+    let upstream = tempfile::tempdir().expect("upstream");
+    let source = upstream.path().join("tool");
+    DemoCrate::new("fn main() { println!(\"v1\"); }\n").write(&source);
+    git(&source, &["init", "--quiet"]);
+    git(&source, &["add", "-A"]);
+    git(&source, &["commit", "--quiet", "-m", "v1"]);
+
+    let home = tempfile::tempdir().expect("home");
+    let elsewhere = tempfile::tempdir().expect("cwd");
+    let url = format!("file://{}", source.display());
+    run_plugin(
+        elsewhere.path(),
+        home.path(),
+        "/usr/bin",
+        None,
+        &["--git", &url],
+    )
+    .success();
+
+    let checkout = home
+        .path()
+        .join(".local/share/cargo-dev-install/checkouts")
+        .join(cargo_dev_install::checkout::checkout_name(&url).expect("checkout name"));
+    let wrapper = home.path().join(".local/bin/demo");
+    let contents = fs::read_to_string(&wrapper).expect("read wrapper");
+    assert!(contents.contains(&format!("REPO=\"{}\"", checkout.display())));
+
+    let run = || {
+        let output = Command::new(&wrapper)
+            .env("HOME", home.path())
+            .env_remove("CARGO_TARGET_DIR")
+            .output()
+            .expect("run wrapper");
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+    assert_eq!(run(), "v1\n");
+
+    write_file(
+        &source.join("src/main.rs"),
+        "fn main() { println!(\"v2\"); }\n",
+    );
+    git(&source, &["commit", "--quiet", "-am", "v2"]);
+    assert_eq!(run(), "v1\n");
+
+    run_plugin(
+        elsewhere.path(),
+        home.path(),
+        "/usr/bin",
+        None,
+        &["update", "demo"],
+    )
+    .success()
+    .stdout(predicate::str::contains("demo: updated"));
+    assert_eq!(run(), "v2\n");
+}
+
+#[test]
+fn git_install_accepts_plain_path_and_branch() {
+    let upstream = tempfile::tempdir().expect("upstream");
+    let source = upstream.path().join("tool");
+    DemoCrate::new("fn main() { println!(\"stable\"); }\n").write(&source);
+    git(&source, &["init", "--quiet"]);
+    git(&source, &["add", "-A"]);
+    git(&source, &["commit", "--quiet", "-m", "stable"]);
+    git(&source, &["branch", "stable"]);
+    write_file(
+        &source.join("src/main.rs"),
+        "fn main() { println!(\"dev\"); }\n",
+    );
+    git(&source, &["commit", "--quiet", "-am", "dev"]);
+
+    let home = tempfile::tempdir().expect("home");
+    run_plugin(
+        upstream.path(),
+        home.path(),
+        "/usr/bin",
+        None,
+        &["--git", "tool", "--branch", "stable"],
+    )
+    .success();
+
+    let output = Command::new(home.path().join(".local/bin/demo"))
+        .env("HOME", home.path())
+        .env_remove("CARGO_TARGET_DIR")
+        .output()
+        .expect("run wrapper");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "stable\n");
+
+    let registry = fs::read_to_string(
+        home.path()
+            .join(".local/share/cargo-dev-install/registry.json"),
+    )
+    .expect("read registry");
+    let source = source.canonicalize().expect("canonicalize");
+    assert!(registry.contains(&format!("\"url\": \"{}\"", source.display())));
+    assert!(registry.contains("\"branch\": \"stable\""));
+}
+
+#[test]
+fn relink_retargets_wrappers_after_checkout_moves() {
+    let root = tempfile::tempdir().expect("root");
+    let old_repo = root.path().join("src/demo");
+    DemoCrate::new(MAIN_ECHO_ARGS).write(&old_repo);
+
+    let home = tempfile::tempdir().expect("home");
+    run_plugin(&old_repo, home.path(), "/usr/bin", None, &[]).success();
+
+    fs::create_dir(root.path().join("work")).expect("create work");
+    let new_repo = root.path().join("work/demo");
+    fs::rename(&old_repo, &new_repo).expect("move checkout");
+
+    run_plugin(
+        root.path(),
+        home.path(),
+        "/usr/bin",
+        None,
+        &["relink", "src", "work"],
+    )
+    .success()
+    .stdout(predicate::str::contains(format!(
+        "demo: {} -> {}",
+        old_repo.display(),
+        new_repo.display()
+    )));
+
+    let wrapper = home.path().join(".local/bin/demo");
+    let contents = fs::read_to_string(&wrapper).expect("read wrapper");
+    assert!(contents.contains(&format!("REPO=\"{}\"", new_repo.display())));
+    let output = Command::new(&wrapper)
+        .env("HOME", home.path())
+        .env("CARGO_TARGET_DIR", new_repo.join("target"))
+        .output()
+        .expect("run wrapper");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "MARKER\n");
+
+    let moved_again = root.path().join("elsewhere/nested/demo");
+    fs::create_dir_all(moved_again.parent().unwrap()).expect("create parent");
+    fs::rename(&new_repo, &moved_again).expect("move checkout again");
+    run_plugin(
+        root.path(),
+        home.path(),
+        "/usr/bin",
+        None,
+        &["relink", "--scan", "elsewhere"],
+    )
+    .success()
+    .stdout(predicate::str::contains(moved_again.display().to_string()));
+    let contents = fs::read_to_string(&wrapper).expect("read wrapper");
+    assert!(contents.contains(&format!("REPO=\"{}\"", moved_again.display())));
+
+    run_plugin(
+        root.path(),
+        home.path(),
+        "/usr/bin",
+        None,
+        &["relink", "/nowhere", "/else"],
+    )
+    .failure()
+    .stderr(predicate::str::contains("no registered wrappers matched"));
+}
+
+#[test]
+fn refresh_rewrites_outdated_wrappers_and_keeps_hand_edits() {
+    let repo = tempfile::tempdir().expect("repo");
+    DemoCrate::new(MAIN_OK).write(repo.path());
+    let home = tempfile::tempdir().expect("home");
+    run_plugin(repo.path(), home.path(), "/usr/bin", None, &[]).success();
+
+    let wrapper = home.path().join(".local/bin/demo");
+    let current = fs::read_to_string(&wrapper).expect("read wrapper");
+    let outdated = current.replacen(
+        &format!("template={} ", cargo_dev_install::install::TEMPLATE_VERSION),
+        "template=0 ",
+        1,
+    );
+    fs::write(&wrapper, &outdated).expect("downgrade wrapper");
+
+    run_plugin(repo.path(), home.path(), "/usr/bin", None, &["refresh"])
+        .success()
+        .stdout(predicate::str::contains("demo: refreshed"))
+        .stdout(predicate::str::contains("-# cargo-dev-install template=0"));
+    assert_eq!(fs::read_to_string(&wrapper).expect("read wrapper"), current);
+
+    let edited = current.replace("--release", "--release --offline");
+    fs::write(&wrapper, &edited).expect("edit wrapper");
+    run_plugin(
+        repo.path(),
+        home.path(),
+        "/usr/bin",
+        None,
+        &["refresh", "--all"],
+    )
+    .success()
+    .stderr(predicate::str::contains("was edited by hand; skipping"));
+    assert_eq!(fs::read_to_string(&wrapper).expect("read wrapper"), edited);
+
+    run_plugin(
+        repo.path(),
+        home.path(),
+        "/usr/bin",
+        None,
+        &["refresh", "--all", "--force"],
+    )
+    .success()
+    .stdout(predicate::str::contains("demo: refreshed"));
+    assert_eq!(fs::read_to_string(&wrapper).expect("read wrapper"), current);
+}
+
+#[test]
+fn reinstall_with_unchanged_wrapper_is_a_no_op() {
+    let repo = tempfile::tempdir().expect("repo");
+    DemoCrate::new(MAIN_OK).write(repo.path());
+    let home = tempfile::tempdir().expect("home");
+    let wrapper = home.path().join(".local/bin/demo");
+
+    run_plugin(repo.path(), home.path(), "/usr/bin", None, &[])
+        .success()
+        .stdout(predicate::str::contains(format!(
+            "installed {}",
+            wrapper.display()
+        )));
+    run_plugin(repo.path(), home.path(), "/usr/bin", None, &[])
+        .success()
+        .stdout(predicate::str::contains("is up to date"));
+
+    run_plugin(
+        repo.path(),
+        home.path(),
+        "/usr/bin",
+        None,
+        &["--toolchain", "nightly"],
+    )
+    .failure()
+    .stderr(predicate::str::contains(
+        "was installed by cargo-dev-install with different settings",
+    ));
+    run_plugin(
+        repo.path(),
+        home.path(),
+        "/usr/bin",
+        None,
+        &["--toolchain", "nightly", "--force"],
+    )
+    .success()
+    .stdout(predicate::str::contains(format!(
+        "updated {}",
+        wrapper.display()
+    )));
+
+    fs::write(&wrapper, "#!/bin/sh\necho mine\n").expect("write foreign file");
+    fs::remove_file(
+        home.path()
+            .join(".local/share/cargo-dev-install/registry.json"),
+    )
+    .expect("forget registration");
+    run_plugin(repo.path(), home.path(), "/usr/bin", None, &[])
+        .failure()
+        .stderr(predicate::str::contains(
+            "was not installed by cargo-dev-install",
+        ));
+}